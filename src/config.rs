@@ -0,0 +1,67 @@
+use std::fs;
+use std::io::{BufReader, Error};
+use std::path::Path;
+
+use mongodb::sync::Client;
+use serde::Deserialize;
+
+use crate::schema::Schema;
+
+// Read from the working directory when there is one and no other config file is given
+pub const DEFAULT_CONFIG_FILE: &str = "config.json";
+pub const DEFAULT_MONGODB_URI: &str = "mongodb://localhost:27017";
+
+// Settings kept in a config file, anything left out falls back to the defaults
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub mongodb: MongoConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MongoConfig {
+    pub uri: Option<String>,
+    pub database: Option<String>,
+    pub collection: Option<String>,
+}
+
+impl Config {
+    // Loads the config file at `path`. Only a file that was asked for by name has to exist
+    pub fn load(path: &Path, required: bool) -> Result<Config, Error> {
+        if !required && !path.exists() {
+            return Ok(Config::default());
+        }
+        let file = fs::File::open(path)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let config = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| Error::other(format!("{}: {}", path.display(), e)))?;
+        return Ok(config);
+    }
+}
+
+// Where messages are imported into and dumped from
+pub struct DatabaseSettings {
+    // A MongoDB connection string, with any authentication, TLS, replica set and pool options in it
+    pub uri: String,
+    // Override the database and collection the schema picks
+    pub database: Option<String>,
+    pub collection: Option<String>,
+}
+
+impl DatabaseSettings {
+    // Creates the client for the database. It only connects once it is used
+    pub fn client(&self) -> Result<Client, Error> {
+        return Client::with_uri_str(&self.uri)
+            .map_err(|e| Error::other(format!("Invalid MongoDB connection string: {}", e)));
+    }
+
+    pub fn apply_to(&self, schema: &mut Schema) {
+        if let Some(database) = &self.database {
+            schema.database = database.clone();
+        }
+        if let Some(collection) = &self.collection {
+            schema.collection = collection.clone();
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::io::Error;
+use std::path::Path;
+
+use rayon::prelude::*;
+
+use crate::helpers::DateRange;
+use crate::http::HttpClient;
+use crate::index::{CrawlIndex, DirectoryEntry, FileEntry};
+use crate::listing::{self, ListingParser};
+use crate::progress::{self, Progress, Unit};
+use crate::throttle::RateLimiter;
+
+// How a crawl walks the archive
+#[derive(Clone, Copy)]
+pub struct Crawler<'a> {
+    pub date_range: DateRange,
+    // A previous crawl whose unchanged directories don't have to be fetched again
+    pub previous: Option<&'a CrawlIndex>,
+    // How many of the newest directories in each listing are always fetched again
+    pub recrawl_newest: usize,
+    pub parser: &'a dyn ListingParser,
+    pub http: &'a HttpClient,
+    // Keeps listing requests to the mirror under a number per second
+    pub request_limit: Option<&'a RateLimiter>,
+    // How many directory listings are fetched at once
+    pub num_workers: usize,
+}
+
+// Everything found in and below one directory
+struct CrawledDirectory {
+    files: Vec<FileEntry>,
+    directories: Vec<DirectoryEntry>,
+    // Whether everything below the directory was indexed
+    complete: bool,
+}
+
+// Collects two lists: a list of files in the current directory and a list of directories
+fn find_files(
+    url: &str,
+    date_range: &DateRange,
+    parser: &dyn ListingParser,
+    http: &HttpClient,
+    request_limit: Option<&RateLimiter>,
+) -> Result<(Vec<FileEntry>, Vec<DirectoryEntry>), Error> {
+    let entries = if url.starts_with("file://") {
+        listing::list_local_directory(url)?
+    } else {
+        if let Some(limit) = request_limit {
+            limit.wait(1.0);
+        }
+        let body = http.get_text(url)?;
+        parser.parse(&body, url)?
+    };
+
+    let mut files = Vec::new();
+    let mut directories = Vec::new();
+    for entry in entries {
+        if entry.is_directory {
+            // Add the next directory's URL to the list to be searched if it may still hold files we want
+            if date_range.may_contain_directory(entry.modified) {
+                directories.push(DirectoryEntry {
+                    url: entry.url,
+                    modified: entry.modified.and_utc(),
+                });
+            }
+        } else if date_range.contains(entry.modified) {
+            // Push the file info into the file vector
+            files.push(FileEntry::new(
+                &entry.name,
+                entry.file_type.as_deref().unwrap_or("File"),
+                entry.size.unwrap_or_default().round() as u64,
+                entry.modified,
+                &entry.url,
+            ));
+        }
+    }
+    return Ok((files, directories));
+}
+
+// Turns what the user gave us to crawl into a url we can join links onto. That is either a http(s)
+// or file url, or a plain path to a local directory which becomes a file url
+pub fn root_url(root: &str) -> Result<String, Error> {
+    let mut url = if ["http://", "https://", "file://"]
+        .iter()
+        .any(|scheme| root.starts_with(scheme))
+    {
+        root.to_string()
+    } else {
+        let path = Path::new(root).canonicalize()?;
+        reqwest::Url::from_directory_path(&path)
+            .map_err(|_| Error::other(format!("Can't crawl {}", path.display())))?
+            .to_string()
+    };
+    // Relative links only resolve against directory urls that end in a slash
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    return Ok(url);
+}
+
+// The `count` most recently modified directories in a listing
+fn newest_directories(directories: &[DirectoryEntry], count: usize) -> Vec<&str> {
+    let mut sorted: Vec<&DirectoryEntry> = directories.iter().collect();
+    sorted.sort_by_key(|dir| dir.modified);
+    return sorted
+        .iter()
+        .rev()
+        .take(count)
+        .map(|dir| dir.url.as_str())
+        .collect();
+}
+
+impl Crawler<'_> {
+    // Recursively indexes every file under `base_url` that was modified within the date range.
+    // Fails only if the base url itself can't be listed, sub directories that fail are logged and left out
+    pub fn crawl(&self, base_url: &str) -> Result<CrawlIndex, Error> {
+        let crawler = Crawler {
+            previous: self
+                .previous
+                .filter(|prev| prev.can_reuse_for(base_url, &self.date_range)),
+            ..*self
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.num_workers)
+            .build()
+            .map_err(Error::other)?;
+        let progress = Progress::new("Crawl", Unit::Directories, 1, None);
+        let crawled = pool.install(|| crawler.crawl_directory(base_url, &progress))?;
+        progress.finish();
+
+        let mut index = CrawlIndex::new(base_url, &self.date_range);
+        index.files = crawled.files;
+        index.directories = crawled.directories;
+        return Ok(index);
+    }
+
+    // Lists `url` and then every directory below it, several at a time. The results are put back
+    // together in listing order so the index comes out the same no matter which fetch finishes first
+    fn crawl_directory(&self, url: &str, progress: &Progress) -> Result<CrawledDirectory, Error> {
+        progress::println(format!("Crawling URL: {}", url));
+        // Collect all files in a directory
+        let (files, directories) = find_files(
+            url,
+            &self.date_range,
+            self.parser,
+            self.http,
+            self.request_limit,
+        )?;
+        progress.advance(1);
+        progress.add_files(files.len() as u64);
+        progress.add_total(directories.len() as u64);
+
+        // The newest directories are always fetched again since files inside of them can grow without the directory changing
+        let newest = newest_directories(&directories, self.recrawl_newest);
+        let children: Vec<Result<CrawledDirectory, Error>> = directories
+            .par_iter()
+            .map(|dir| {
+                // A directory that hasn't changed since the last crawl can be copied over with everything below it
+                let unchanged = self
+                    .previous
+                    .filter(|_| !newest.contains(&dir.url.as_str()))
+                    .filter(|prev| {
+                        prev.find_directory(&dir.url)
+                            .is_some_and(|cached| cached.modified == dir.modified)
+                    });
+                match unchanged {
+                    Some(prev) => {
+                        progress::println(format!("Reusing unchanged directory: {}", dir.url));
+                        let files: Vec<FileEntry> = prev
+                            .files_under(&dir.url, &self.date_range)
+                            .cloned()
+                            .collect();
+                        progress.advance(1);
+                        progress.add_files(files.len() as u64);
+                        return Ok(CrawledDirectory {
+                            files,
+                            directories: prev.directories_under(&dir.url).cloned().collect(),
+                            complete: true,
+                        });
+                    }
+                    // Run a recursive scan of the sub directory and collect all files
+                    None => return self.crawl_directory(&dir.url, progress),
+                }
+            })
+            .collect();
+
+        let mut crawled = CrawledDirectory {
+            files,
+            directories: Vec::new(),
+            complete: true,
+        };
+        for (dir, child) in directories.iter().zip(children) {
+            match child {
+                Ok(child) => {
+                    // Directories that weren't fully indexed aren't recorded so the next crawl doesn't mistake them for unchanged
+                    if child.complete {
+                        crawled.directories.push(dir.clone());
+                    }
+                    crawled.files.extend(child.files);
+                    crawled.directories.extend(child.directories);
+                    crawled.complete &= child.complete;
+                }
+                Err(e) => {
+                    // The directory was never listed, but it's done with
+                    progress.advance(1);
+                    progress::println(format!("Error crawling {}: {}", dir.url, e));
+                    crawled.complete = false;
+                }
+            }
+        }
+
+        progress::println(format!("Indexed {} files in {}", crawled.files.len(), url));
+        return Ok(crawled);
+    }
+}
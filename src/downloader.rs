@@ -1,77 +1,719 @@
+use bzip2::read::MultiBzDecoder;
+use chrono::Utc;
+use clap::ValueEnum;
 use rayon::prelude::*;
-use reqwest;
+use reqwest::blocking::Response;
+use reqwest::header::{
+    HeaderName, CONTENT_RANGE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED,
+    RANGE,
+};
+use reqwest::{StatusCode, Url};
 use std::fs;
-use std::io::{Error, ErrorKind, Write};
-use std::path::Path;
+use std::io::{self, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
 
-fn download_file(url: &str, file_name: &str) -> Result<(), Error> {
-    // Make the HTTP GET request using a fresh client (fixes issues where we cannot download in parallel)
-    // Fuck async reqwest, all my homies hate managing async contexts
-    let client = reqwest::blocking::Client::new();
-    let resp = client.get(url).send();
+use crate::helpers;
+use crate::http::HttpClient;
+use crate::index::FileEntry;
+use crate::manifest::{self, FileStatus, Manifest, ManifestEntry};
+use crate::progress::{self, Progress, Unit};
+use crate::throttle::{RateLimiter, ThrottledReader};
 
-    if !resp.is_err() {
-        let response = resp.unwrap();
-        // Try to create the downloads directory. This should always work the first time but if it doesn't it could break the code...
+// How files from a local (file://) source end up in the downloads directory
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum LinkMode {
+    /// Copy the file
+    Copy,
+    /// Hardlink the file, falling back to a copy if the source is on another filesystem
+    Hardlink,
+}
 
-        if !Path::exists(Path::new("downloads/")) {
-            let _ = fs::create_dir("downloads/");
+// "Downloads" a file from a local source by copying or hardlinking it to `target`. Returns the size the
+// source file has now, which is what the transferred file has to match
+fn transfer_local_file(url: &str, target: &Path, link_mode: LinkMode) -> Result<u64, Error> {
+    let source = Url::parse(url)
+        .ok()
+        .and_then(|parsed| parsed.to_file_path().ok())
+        .ok_or_else(|| Error::other(format!("Not a local file url: {}", url)))?;
+
+    if let LinkMode::Hardlink = link_mode {
+        progress::println(format!("Linking file: {}", source.display()));
+        match fs::hard_link(&source, target) {
+            Ok(_) => return Ok(fs::metadata(&source)?.len()),
+            Err(e) => progress::println(format!(
+                "Could not hardlink {}, copying it instead: {}",
+                source.display(),
+                e
+            )),
         }
+    }
+    progress::println(format!("Copying file: {}", source.display()));
+    fs::copy(&source, target)?;
+    return Ok(fs::metadata(&source)?.len());
+}
 
-        let full_path = "downloads/".to_owned() + file_name;
-        let full_processed_path = "downloads/processed/".to_owned() + file_name;
+// Downloads are written under this extension and only renamed into place once they are complete
+const PART_EXTENSION: &str = ".part";
 
-        if Path::exists(Path::new(&full_path)) || Path::exists(Path::new(&full_processed_path)) {
-            println!("Skipping existing file: {}", file_name);
-            return Ok(());
+// Next to a partial download, the ETag or Last-Modified of the file it is part of. Resuming only carries
+// on from the partial file if the file on the server still has it
+const VALIDATOR_EXTENSION: &str = ".part.validator";
+
+// Where downloads that failed their checks are moved to, inside of the downloads directory
+const QUARANTINE_DIR: &str = "quarantine";
+
+// How much of a download is buffered before it is written to disk
+const CHUNK_SIZE: usize = 256 * 1024;
+
+// Whether a file in the downloads directory is (or belongs to) an unfinished download
+pub fn is_partial_download(file_name: &str) -> bool {
+    return file_name.ends_with(PART_EXTENSION) || file_name.ends_with(VALIDATOR_EXTENSION);
+}
+
+fn part_path(full_path: &Path) -> PathBuf {
+    let mut part = full_path.as_os_str().to_owned();
+    part.push(PART_EXTENSION);
+    return PathBuf::from(part);
+}
+
+fn validator_path(full_path: &Path) -> PathBuf {
+    let mut validator = full_path.as_os_str().to_owned();
+    validator.push(VALIDATOR_EXTENSION);
+    return PathBuf::from(validator);
+}
+
+// Removes the partial download of the file at `full_path` along with its validator
+fn remove_partial_download(full_path: &Path) -> Result<(), Error> {
+    for path in [part_path(full_path), validator_path(full_path)] {
+        if path.exists() {
+            fs::remove_file(path)?;
         }
-        // Open a file to write the downloaded content
-        let mut file =
-            std::fs::File::create(full_path).expect(&format!("Error creating file: {}", file_name));
+    }
+    return Ok(());
+}
 
-        println!("Downloading file: {}", url);
-        let content = response.bytes();
-        if !content.is_err() {
-            file.write(&content.unwrap())?;
-        } else {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Error when writing content to file: {}",
-                    content.unwrap_err()
-                ),
-            )
-            .into());
+// What to send as If-Range when resuming the download of this response. Weak ETags can't be used for
+// that, so those fall back to the Last-Modified
+fn resume_validator(response: &Response) -> Option<String> {
+    return header_value(response, ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header_value(response, LAST_MODIFIED));
+}
+
+// Creates an empty partial file for `full_path`, next to the validator of the response it is from
+fn start_partial_download(full_path: &Path, response: &Response) -> Result<fs::File, Error> {
+    let validator = validator_path(full_path);
+    match resume_validator(response) {
+        Some(value) => fs::write(&validator, value)?,
+        None if validator.exists() => fs::remove_file(&validator)?,
+        None => {}
+    }
+    return fs::File::create(part_path(full_path));
+}
+
+// The first byte of a 206 response and the size of the whole file, from a Content-Range header like
+// "bytes 100-199/200". The size is left out when the server doesn't know it ("bytes 100-199/*")
+fn content_range(response: &Response) -> Option<(u64, Option<u64>)> {
+    let value = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let start = range.split('-').next()?.trim().parse().ok()?;
+    return Some((start, total.trim().parse().ok()));
+}
+
+// Checks a finished download before it is put in place. Returns why the file is no good, if it isn't.
+// `expected_size` is the size the server (or the local source) gave for the file as it was transferred.
+// Without one the listing has to do, though it can be out of date for a file that is still growing
+fn verify_download(
+    path: &Path,
+    file: &FileEntry,
+    expected_size: Option<u64>,
+) -> Result<Option<String>, Error> {
+    let size = fs::metadata(path)?.len();
+    match expected_size {
+        Some(expected) if size != expected => {
+            return Ok(Some(format!(
+                "Size is {} bytes but the server said {} bytes",
+                size, expected
+            )));
         }
+        None if size.abs_diff(file.size) > helpers::size_tolerance(file.size) => {
+            return Ok(Some(format!(
+                "Size is {} bytes but the listing said {} bytes",
+                size, file.size
+            )));
+        }
+        _ => {}
+    }
+    // Corrupt archives would otherwise only turn up once the importer chokes on them. An archive that was
+    // added to over the day is several bz2 streams one after the other, every one of them has to decompress
+    if file.name.ends_with(".bz2") {
+        let mut decoder = MultiBzDecoder::new(fs::File::open(path)?);
+        if let Err(e) = io::copy(&mut decoder, &mut io::sink()) {
+            return Ok(Some(format!("Archive is corrupt: {}", e)));
+        }
+    }
+    return Ok(None);
+}
+
+// What came of fetching a file over http
+enum Fetched {
+    // The server says the file didn't change since we last downloaded it
+    NotModified,
+    Complete {
+        etag: Option<String>,
+        last_modified: Option<String>,
+        // The size of the whole file according to the server
+        size: Option<u64>,
+    },
+    // The connection broke while the body was coming in, what made it is in the partial file
+    Interrupted(String),
+}
+
+fn header_value(response: &Response, name: HeaderName) -> Option<String> {
+    return response
+        .headers()
+        .get(name)?
+        .to_str()
+        .ok()
+        .map(|value| value.to_string());
+}
+
+// How downloading a file went
+pub enum DownloadOutcome {
+    Downloaded,
+    // We already had the file and it hasn't changed
+    Skipped,
+    // Why the file couldn't be downloaded
+    Failed(String),
+}
+
+// How every file of a download run went, in the order they were given
+pub struct DownloadReport<'a> {
+    pub outcomes: Vec<(&'a FileEntry, DownloadOutcome)>,
+}
+
+impl DownloadReport<'_> {
+    fn count(&self, matches: fn(&DownloadOutcome) -> bool) -> usize {
+        return self.outcomes.iter().filter(|(_, x)| matches(x)).count();
+    }
+
+    pub fn downloaded(&self) -> usize {
+        return self.count(|x| matches!(x, DownloadOutcome::Downloaded));
+    }
+
+    pub fn skipped(&self) -> usize {
+        return self.count(|x| matches!(x, DownloadOutcome::Skipped));
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = (&FileEntry, &str)> {
+        return self
+            .outcomes
+            .iter()
+            .filter_map(|(file, outcome)| match outcome {
+                DownloadOutcome::Failed(reason) => return Some((*file, reason.as_str())),
+                _ => return None,
+            });
+    }
+}
+
+// How downloads are fetched and where they end up
+pub struct Downloader<'a> {
+    pub download_dir: &'a Path,
+    pub link_mode: LinkMode,
+    pub http: &'a HttpClient,
+    // Caps the bytes per second of all downloads together
+    pub bandwidth: Option<&'a RateLimiter>,
+    // How many files are downloaded at once
+    pub num_workers: usize,
+}
+
+impl Downloader<'_> {
+    fn quarantine_dir(&self) -> PathBuf {
+        return self.download_dir.join(QUARANTINE_DIR);
+    }
+
+    // Moves a download that failed its checks out of the way, with a note saying what was wrong with it
+    fn quarantine(&self, part: &Path, file: &FileEntry, reason: &str) -> Result<(), Error> {
+        let quarantine_dir = self.quarantine_dir();
+        fs::create_dir_all(&quarantine_dir)?;
+        let target = quarantine_dir.join(&file.name);
+        progress::println(format!("Quarantining {}: {}", file.name, reason));
+        fs::rename(part, &target)?;
+        fs::write(
+            quarantine_dir.join(format!("{}.reason", file.name)),
+            format!("{}\n{}\n", file.url, reason),
+        )?;
         return Ok(());
     }
-    return Err(Error::new(
-        ErrorKind::Other,
-        format!("Request failed with status: {}", resp.unwrap().status()),
-    )
-    .into());
-}
-
-pub fn download_files_in_parallel(
-    urls: &Vec<&str>,
-    file_names: &Vec<&str>,
-    num_workers: usize,
-) -> Result<(), Error> {
-    // Zip the URLs and file names together
-    let pairs: Vec<_> = urls.iter().zip(file_names.iter()).collect();
-    let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(num_workers)
-        .build()
-        .unwrap();
-
-    // Download the files in parallel
-    pool.install(|| {
-        pairs.par_iter().for_each(|(url, file_name)| {
-            if let Err(err) = download_file(url, file_name) {
-                eprintln!("Error downloading {}: {}", url, err);
+
+    // Fetches `url` into the partial file for `full_path` over http, carrying on from whatever is already
+    // in it. With a `previous` record the server is asked to only send the file if it changed since then.
+    // `counted` is how much of the partial file is already in the progress, from an earlier try in this run
+    fn fetch(
+        &self,
+        url: &str,
+        full_path: &Path,
+        previous: Option<&ManifestEntry>,
+        progress: &Progress,
+        counted: u64,
+    ) -> Result<Fetched, Error> {
+        let part = part_path(full_path);
+        // Whatever made it into the partial file last time doesn't have to be fetched again, as long as
+        // we can tell the server which version of the file it is from
+        let mut offset = fs::metadata(&part).map(|meta| meta.len()).unwrap_or(0);
+        let validator = fs::read_to_string(validator_path(full_path)).ok();
+        if offset > 0 && validator.is_none() {
+            progress::println(format!(
+                "Don't know which version of {} the partial download is from, downloading it again",
+                url
+            ));
+            remove_partial_download(full_path)?;
+            offset = 0;
+        }
+        let response = self.http.send(url, |client| {
+            let mut request = client.get(url);
+            if let Some(validator) = validator.as_ref().filter(|_| offset > 0) {
+                // The server sends the whole file instead of the rest if it changed since the partial file was started
+                request = request
+                    .header(RANGE, format!("bytes={}-", offset))
+                    .header(IF_RANGE, validator.trim());
             }
+            if let Some(etag) = previous.and_then(|x| x.etag.as_ref()) {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = previous.and_then(|x| x.last_modified.as_ref()) {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+            return request;
+        })?;
+
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+        if offset > 0 && status == StatusCode::RANGE_NOT_SATISFIABLE {
+            // The partial file is no good to us (the file may have shrunk on the server), start over
+            progress::println(format!("Can't resume {}, downloading it again", url));
+            remove_partial_download(full_path)?;
+            return self.fetch(url, full_path, previous, progress, counted);
+        }
+        // Anything else that isn't a success is an error page, not the file
+        if !status.is_success() {
+            return Err(Error::other(format!("Server returned {}", status)));
+        }
+
+        let resumed = content_range(&response).filter(|_| status == StatusCode::PARTIAL_CONTENT);
+        let size = match resumed {
+            Some((_, total)) => total,
+            None => response.content_length(),
+        };
+        let file = if offset == 0 {
+            progress::println(format!("Downloading file: {}", url));
+            start_partial_download(full_path, &response)?
+        } else if status == StatusCode::PARTIAL_CONTENT {
+            if resumed.map(|(start, _)| start) != Some(offset) {
+                remove_partial_download(full_path)?;
+                return Err(Error::other(format!(
+                    "Server sent the wrong range when resuming {}, it will be downloaded again",
+                    url
+                )));
+            }
+            progress::println(format!("Resuming download from {} bytes: {}", offset, url));
+            progress.advance(offset.saturating_sub(counted));
+            fs::OpenOptions::new().append(true).open(&part)?
+        } else {
+            // Either the file changed since the partial file was started or the server ignored the range,
+            // both ways it sent everything
+            progress::println(format!(
+                "File changed on the server or it doesn't support resuming, downloading it again: {}",
+                url
+            ));
+            start_partial_download(full_path, &response)?
+        };
+
+        let etag = header_value(&response, ETAG);
+        let last_modified = header_value(&response, LAST_MODIFIED);
+        // Stream the body to disk in chunks so memory use doesn't grow with the size of the file
+        let mut writer = BufWriter::with_capacity(CHUNK_SIZE, file);
+        let mut reader = ThrottledReader {
+            inner: progress.reader(response),
+            limiter: self.bandwidth,
+        };
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let interrupted = loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break None,
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => break Some(e.to_string()),
+            };
+            writer
+                .write_all(&buffer[..read])
+                .map_err(|e| Error::other(format!("Error when writing content to file: {}", e)))?;
+        };
+        // Whatever came in before a break is kept for the next try to carry on from
+        writer.flush()?;
+        writer.into_inner().map_err(Error::other)?.sync_all()?;
+        if let Some(reason) = interrupted {
+            return Ok(Fetched::Interrupted(reason));
+        }
+        return Ok(Fetched::Complete {
+            etag,
+            last_modified,
+            size,
         });
-    });
+    }
 
-    return Ok(());
+    // Fetches a file, resuming it from the partial file for as long as the retry policy allows when
+    // the connection breaks part way through the body. Only gives up with Interrupted once out of attempts
+    fn fetch_with_retries(
+        &self,
+        url: &str,
+        full_path: &Path,
+        previous: Option<&ManifestEntry>,
+        progress: &Progress,
+    ) -> Result<Fetched, Error> {
+        let retry = self.http.retry_policy();
+        let mut attempt = 1;
+        let mut counted = 0;
+        loop {
+            let reason = match self.fetch(url, full_path, previous, progress, counted)? {
+                Fetched::Interrupted(reason) => reason,
+                fetched => return Ok(fetched),
+            };
+            counted = fs::metadata(part_path(full_path))
+                .map(|meta| meta.len())
+                .unwrap_or(0);
+            if attempt >= retry.max_attempts {
+                return Ok(Fetched::Interrupted(format!(
+                    "broke off {} times, last time with: {}",
+                    attempt, reason
+                )));
+            }
+            let delay = retry.backoff(attempt - 1);
+            progress::println(format!(
+                "Download of {} broke off ({}), resuming in {:.1}s (attempt {}/{})",
+                url,
+                reason,
+                delay.as_secs_f64(),
+                attempt + 1,
+                retry.max_attempts
+            ));
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    fn download_file(
+        &self,
+        file: &FileEntry,
+        manifest: &Mutex<Manifest>,
+        progress: &Progress,
+    ) -> Result<DownloadOutcome, Error> {
+        let download_dir = self.download_dir;
+        // Try to create the downloads directory. This should always work the first time but if it doesn't it could break the code...
+        if !download_dir.exists() {
+            let _ = fs::create_dir_all(download_dir);
+        }
+
+        let full_path = download_dir.join(&file.name);
+        let full_processed_path = download_dir.join("processed").join(&file.name);
+        let existing = [&full_path, &full_processed_path]
+            .into_iter()
+            .find(|path| path.exists());
+        // Only an entry for a file we actually downloaded says anything about the file we have
+        let previous = manifest
+            .lock()
+            .unwrap()
+            .files
+            .get(&file.name)
+            .filter(|entry| entry.downloaded_at.is_some())
+            .cloned();
+
+        // Files we already have are only fetched again when they changed on the server, like the archive for
+        // the current day which keeps growing until the day is over
+        if let Some(existing) = existing {
+            let size = fs::metadata(existing)?.len();
+            match &previous {
+                Some(entry) if entry.matches_listing(file) => {
+                    progress::println(format!("Skipping unchanged file: {}", file.name));
+                    progress.advance(file.size);
+                    return Ok(DownloadOutcome::Skipped);
+                }
+                // Downloaded before the manifest kept track, it is taken as up to date if the size still fits the listing
+                None if size.abs_diff(file.size) <= helpers::size_tolerance(file.size) => {
+                    progress::println(format!("Skipping existing file: {}", file.name));
+                    progress.advance(file.size);
+                    let mut manifest = manifest.lock().unwrap();
+                    let entry = manifest.entry(file);
+                    entry.status = if existing == &full_processed_path {
+                        FileStatus::Imported
+                    } else {
+                        FileStatus::Downloaded
+                    };
+                    entry.downloaded_size = Some(size);
+                    entry.downloaded_at = Some(Utc::now());
+                    return Ok(DownloadOutcome::Skipped);
+                }
+                _ => progress::println(format!("Checking changed file: {}", file.name)),
+            }
+        }
+
+        // The file only gets its real name once it is complete and checked, so a download that is cut off
+        // or broken never looks like a finished file
+        let part = part_path(&full_path);
+        let (etag, last_modified, expected_size) = if file.url.starts_with("file://") {
+            // Local files are quick to bring in again, and a leftover partial file would get in the way of a hardlink
+            remove_partial_download(&full_path)?;
+            let size = transfer_local_file(&file.url, &part, self.link_mode)?;
+            progress.advance(file.size);
+            (None, None, Some(size))
+        } else {
+            // A partial file from before the file changed can't be resumed
+            let conditions = existing.and(previous.as_ref());
+            if existing.is_some() {
+                remove_partial_download(&full_path)?;
+            }
+            match self.fetch_with_retries(&file.url, &full_path, conditions, progress)? {
+                Fetched::NotModified => {
+                    progress.advance(file.size);
+                    progress::println(format!("File hasn't changed on the server: {}", file.name));
+                    let mut manifest = manifest.lock().unwrap();
+                    let entry = manifest.entry(file);
+                    entry.expected_size = file.size;
+                    entry.modified = file.modified;
+                    entry.last_error = None;
+                    return Ok(DownloadOutcome::Skipped);
+                }
+                Fetched::Complete {
+                    etag,
+                    last_modified,
+                    size,
+                } => (etag, last_modified, size),
+                // The partial file stays for the next run to carry on from
+                Fetched::Interrupted(reason) => {
+                    return Err(Error::other(format!("Download {}", reason)));
+                }
+            }
+        };
+
+        if let Some(reason) = verify_download(&part, file, expected_size)? {
+            self.quarantine(&part, file, &reason)?;
+            remove_partial_download(&full_path)?;
+            return Err(Error::other(format!(
+                "Download failed its checks: {}",
+                reason
+            )));
+        }
+        let size = fs::metadata(&part)?.len();
+        let checksum = manifest::checksum(&part)?;
+        fs::rename(&part, &full_path)?;
+        remove_partial_download(&full_path)?;
+        // A refreshed file that was already imported goes back in line to be imported again
+        if full_processed_path.exists() {
+            progress::println(format!("Refreshed processed file: {}", file.name));
+            fs::remove_file(&full_processed_path)?;
+        }
+
+        let mut manifest = manifest.lock().unwrap();
+        let entry = manifest.entry(file);
+        *entry = ManifestEntry {
+            status: FileStatus::Downloaded,
+            downloaded_size: Some(size),
+            checksum: Some(checksum),
+            downloaded_at: Some(Utc::now()),
+            etag,
+            last_modified,
+            imported_at: entry.imported_at,
+            ..ManifestEntry::new(file)
+        };
+        return Ok(DownloadOutcome::Downloaded);
+    }
+
+    // Removes partial downloads left behind by an earlier run for files we aren't downloading now.
+    // The others are kept so their downloads can pick up where they left off
+    fn remove_stale_partial_downloads(&self, files: &[&FileEntry]) -> Result<(), Error> {
+        if !self.download_dir.exists() {
+            return Ok(());
+        }
+        for entry in fs::read_dir(self.download_dir)? {
+            let path = entry?.path();
+            let is_partial = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(is_partial_download);
+            let wanted = files.iter().any(|file| {
+                let full_path = self.download_dir.join(&file.name);
+                path == part_path(&full_path) || path == validator_path(&full_path)
+            });
+            if is_partial && !wanted && path.is_file() {
+                progress::println(format!("Removing partial download: {}", path.display()));
+                fs::remove_file(&path)?;
+            }
+        }
+        return Ok(());
+    }
+
+    // Downloads every file, several at a time. Files that fail don't stop the others, the report says how each went
+    pub fn download_files<'f>(&self, files: &[&'f FileEntry]) -> Result<DownloadReport<'f>, Error> {
+        self.remove_stale_partial_downloads(files)?;
+        let manifest = Mutex::new(Manifest::load(self.download_dir)?);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.num_workers)
+            .build()
+            .unwrap();
+
+        let total_size = files.iter().map(|file| file.size).sum();
+        let progress = Progress::new(
+            "Download",
+            Unit::Bytes,
+            total_size,
+            Some(files.len() as u64),
+        );
+        // Download the files in parallel
+        let outcomes = pool.install(|| {
+            files
+                .par_iter()
+                .map(|file| {
+                    let outcome = match self.download_file(file, &manifest, &progress) {
+                        Ok(outcome) => outcome,
+                        Err(err) => {
+                            progress::eprintln(format!("Error downloading {}: {}", file.url, err));
+                            let mut manifest = manifest.lock().unwrap();
+                            let entry = manifest.entry(file);
+                            // A file we already have stays usable when refreshing it failed
+                            if entry.downloaded_at.is_none() {
+                                entry.status = FileStatus::DownloadFailed;
+                            }
+                            entry.last_error = Some(err.to_string());
+                            DownloadOutcome::Failed(err.to_string())
+                        }
+                    };
+                    // Saved after every file so a run that gets killed doesn't lose track of what it did
+                    if let Err(e) = manifest.lock().unwrap().save(self.download_dir) {
+                        progress::eprintln(format!("Could not save the manifest: {}", e));
+                    }
+                    progress.finish_file(matches!(outcome, DownloadOutcome::Failed(_)));
+                    return (*file, outcome);
+                })
+                .collect()
+        });
+        progress.finish();
+
+        manifest.into_inner().unwrap().save(self.download_dir)?;
+        return Ok(DownloadReport { outcomes });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bzip2::write::BzEncoder;
+    use bzip2::Compression;
+    use chrono::NaiveDateTime;
+
+    fn response(headers: &[(&str, &str)]) -> Response {
+        let mut builder = http::Response::builder().status(206);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        return Response::from(builder.body(String::new()).unwrap());
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = BzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        return encoder.finish().unwrap();
+    }
+
+    // Writes `contents` to a file of its own under the temp directory and checks it like a finished download
+    fn verify(name: &str, contents: &[u8], expected_size: Option<u64>) -> Option<String> {
+        let dir = std::env::temp_dir().join(format!("eddn_indexer_verify_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        let file = FileEntry::new(
+            name,
+            "File",
+            contents.len() as u64,
+            NaiveDateTime::default(),
+            &format!("https://example.com/{}", name),
+        );
+        let result = verify_download(&path, &file, expected_size).unwrap();
+        fs::remove_file(&path).unwrap();
+        return result;
+    }
+
+    #[test]
+    fn verify_download_checks_the_size_the_server_gave() {
+        assert_eq!(verify("a.txt", b"hello", Some(5)), None);
+        assert!(verify("b.txt", b"hello", Some(6)).is_some());
+        // Without one the listing size has to do, within a unit of it
+        assert_eq!(verify("c.txt", b"hello", None), None);
+    }
+
+    #[test]
+    fn verify_download_checks_every_bz2_stream() {
+        let mut archive = compress(b"{\"first\":1}\n");
+        archive.extend(compress(b"{\"second\":2}\n"));
+        assert_eq!(verify("whole.jsonl.bz2", &archive, None), None);
+
+        // A second stream that was cut short is as broken as a first one
+        let second = compress(b"{\"second\":2}\n");
+        archive.truncate(archive.len() - second.len());
+        archive.extend(&second[..second.len() / 2]);
+        assert!(verify("cut.jsonl.bz2", &archive, None)
+            .unwrap()
+            .starts_with("Archive is corrupt"));
+    }
+
+    #[test]
+    fn content_range_reads_the_start_and_size() {
+        let resumed = response(&[("Content-Range", "bytes 100-199/200")]);
+        assert_eq!(content_range(&resumed), Some((100, Some(200))));
+        // The server may not know how big the whole file is
+        let unknown = response(&[("Content-Range", "bytes 100-199/*")]);
+        assert_eq!(content_range(&unknown), Some((100, None)));
+    }
+
+    #[test]
+    fn content_range_needs_a_byte_range() {
+        assert_eq!(content_range(&response(&[])), None);
+        for value in [
+            "items 100-199/200",
+            "bytes */200",
+            "bytes 100-199",
+            "bytes x-199/200",
+        ] {
+            let resumed = response(&[("Content-Range", value)]);
+            assert_eq!(content_range(&resumed), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn resume_validator_prefers_a_strong_etag() {
+        let both = response(&[
+            ("ETag", "\"abc\""),
+            ("Last-Modified", "Fri, 05 Jan 2024 00:00:00 GMT"),
+        ]);
+        assert_eq!(resume_validator(&both), Some("\"abc\"".to_string()));
+    }
+
+    #[test]
+    fn resume_validator_falls_back_to_last_modified() {
+        let weak = response(&[
+            ("ETag", "W/\"abc\""),
+            ("Last-Modified", "Fri, 05 Jan 2024 00:00:00 GMT"),
+        ]);
+        assert_eq!(
+            resume_validator(&weak),
+            Some("Fri, 05 Jan 2024 00:00:00 GMT".to_string())
+        );
+        // A weak ETag alone can't be used to resume at all
+        assert_eq!(resume_validator(&response(&[("ETag", "W/\"abc\"")])), None);
+        assert_eq!(resume_validator(&response(&[])), None);
+    }
 }
@@ -0,0 +1,117 @@
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+use bson::{doc, Document};
+use chrono::prelude::*;
+use mongodb::{options::FindOptions, sync::Client, sync::Collection};
+use serde_json::to_writer_pretty;
+
+use crate::helpers;
+use crate::schema::Schema;
+
+// Queries the database for installation signals and dumps the newest one per star system to `output`.
+// Returns the number of unique signals that were written.
+pub fn dump_installations(client: &Client, schema: &Schema, output: &Path) -> Result<usize, Error> {
+    let db = client.database(&schema.database);
+    let collection: Collection<Document> = db.collection(&schema.collection);
+
+    // Define the query
+    let query = doc! {"message.signals.SignalType": "Installation"};
+
+    // Define the projection
+    let projection = doc! {
+        "message.StarSystem": 1,
+        "message.SystemAddress": 1,
+        "message.StarPos": 1,
+        "message.signals": {
+            "$filter": {
+                "input": "$message.signals",
+                "as": "signal",
+                "cond": {
+                    "$eq": ["$$signal.SignalType", "Installation"]
+                }
+            }
+        }
+    };
+
+    println!("Generating query...");
+    let find_options = FindOptions::builder().projection(projection).build();
+    let signals = collection
+        .find(query)
+        .with_options(find_options)
+        .run()
+        .map_err(Error::other)?;
+    // Create a new JSON Value to store unique signals
+    let mut unique_signals = serde_json::Map::new();
+
+    println!("Filtering results...");
+
+    let mut i = 0;
+    let mut has_printed = false;
+    // Iterate over the results
+    for result in signals {
+        match result {
+            Ok(mut signal) => {
+                // Remove the _id key from the signal
+                signal.remove("_id");
+
+                // Convert the signal to a serde_json::Value
+                let data: serde_json::Value = serde_json::from_str(&signal.to_string()).unwrap();
+
+                // Get the StarSystem from the message key in the data
+                let star_system = &data["message"]["StarSystem"].as_str().unwrap().to_string();
+
+                // Check if the star system is in the unique_signals JSON value
+                if !unique_signals.contains_key(star_system) {
+                    // If it isn't, add it with the data as the value
+                    unique_signals.insert(star_system.to_string(), data);
+                    // Print a message every 100 results
+                    i += 1;
+                    has_printed = false;
+                } else {
+                    // If it is, check the timestamp of the current signal and the one in unique_signals
+                    let current_timestamp = &data["message"]["signals"][0]["timestamp"];
+                    let stored_timestamp =
+                        &unique_signals[star_system]["message"]["signals"][0]["timestamp"];
+
+                    // Parse the timestamps as NaiveDate
+                    let current_date = NaiveDate::parse_from_str(
+                        current_timestamp.as_str().unwrap(),
+                        "%Y-%m-%dT%H:%M:%SZ",
+                    )
+                    .unwrap();
+                    let stored_date = NaiveDate::parse_from_str(
+                        stored_timestamp.as_str().unwrap(),
+                        "%Y-%m-%dT%H:%M:%SZ",
+                    )
+                    .unwrap();
+
+                    // If the current signal is newer, replace the one in unique_signals
+                    if helpers::date_is_after(current_date, stored_date) {
+                        unique_signals.insert(star_system.to_string(), data);
+                        i += 1;
+                        has_printed = false;
+                    }
+                }
+                // Print a message every 1000 results we process
+                if i % 1000 == 0 && !has_printed {
+                    println!(
+                        "Processed {} total items that are new or should replace existing ones...",
+                        i
+                    );
+                    has_printed = true;
+                }
+            }
+            Err(e) => println!("Error processing document: {:?}", e),
+        }
+    }
+
+    // Print a message with the number of signals remaining in unique_signals
+    println!("Number of unique signals: {}", unique_signals.len());
+    println!("Dumping to disk as requested...");
+    // Create the output file and dump the json data to it
+    let file = fs::File::create(output)?;
+    to_writer_pretty(file, &unique_signals)?;
+    return Ok(unique_signals.len());
+}
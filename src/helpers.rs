@@ -1,26 +1,56 @@
+use std::fs;
 use std::io;
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-use chrono::{NaiveDate, TimeDelta};
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+
+// Saves `value` as JSON to a temporary file next to `path` and then moves it over `path`, so a crash
+// part way through never leaves a torn file behind
+pub fn save_json<T: serde::Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let mut writer = BufWriter::new(fs::File::create(&temp)?);
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    fs::rename(&temp, path)?;
+    return Ok(());
+}
 
 const SIZE_STRINGS: [&str; 4] = ["KB", "MB", "GB", "TB"];
 
-pub fn string_to_bytes_value(string: String) -> f64 {
-    // Copy the string so we can mutate it.
-    let mut str_copy = string.clone();
-    // Remove the last char (the size character) and save it for later
-    let size_char = str_copy.pop().unwrap();
+// Turns a listing size like "12.3M" or "12345" into bytes. Returns None for sizes like "-" that directories get
+pub fn string_to_bytes_value(string: &str) -> Option<f64> {
+    let string = string.trim();
+    // Split off the last char (the size character) if there is one
+    let (value, size_char) = match string.chars().last() {
+        Some(c) if c.is_ascii_alphabetic() => (&string[..string.len() - 1], c),
+        _ => (string, 'B'),
+    };
     // Parse the str as a float
-    let size_value: f64 = str_copy.parse().unwrap();
+    let size_value: f64 = value.trim().parse().ok()?;
     // Find the correct multiplier
-    let multiplier = match size_char {
-        'K' => 1024,
-        'M' => 1048576,
-        'G' => 1073741824,
-        _ => 1,
+    let multiplier: f64 = match size_char.to_ascii_uppercase() {
+        'K' => 1024.0,
+        'M' => 1048576.0,
+        'G' => 1073741824.0,
+        'T' => 1099511627776.0,
+        _ => 1.0,
     };
     // Calculate the bytes value
-    return size_value * multiplier as f64;
+    return Some(size_value * multiplier);
+}
+
+// How far a real file size may be off from the size a listing gave for it. Listings round to their
+// unit ("12.3M" or even "12M"), so allow up to one whole unit of it, and at least 1K for tiny files
+pub fn size_tolerance(listed_size: u64) -> u64 {
+    let mut unit: u64 = 1024;
+    while unit < 1099511627776 && listed_size >= unit * 1024 {
+        unit *= 1024;
+    }
+    return unit;
 }
 
 pub fn bytes_value_to_size_string(bytes_value: f64) -> String {
@@ -30,7 +60,7 @@ pub fn bytes_value_to_size_string(bytes_value: f64) -> String {
 
     while val >= 1024.0 && i < 4 {
         i += 1;
-        val = val / 1024.0;
+        val /= 1024.0;
     }
 
     ret.push_str(&format!("{:.5}", val));
@@ -42,6 +72,27 @@ pub fn date_is_after(to_check: NaiveDate, reference: NaiveDate) -> bool {
     return (to_check - reference) > TimeDelta::new(0, 0).unwrap();
 }
 
+// A window of listing timestamps. The start is inclusive and the end is exclusive, either side may be left open
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DateRange {
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+impl DateRange {
+    pub fn contains(&self, timestamp: NaiveDateTime) -> bool {
+        let after_start = self.since.is_none_or(|since| timestamp >= since);
+        let before_end = self.until.is_none_or(|until| timestamp < until);
+        return after_start && before_end;
+    }
+
+    // A directory's timestamp is the last time something changed inside of it,
+    // so anything modified after the start may still hold files that are in range
+    pub fn may_contain_directory(&self, timestamp: NaiveDateTime) -> bool {
+        return self.since.is_none_or(|since| timestamp >= since);
+    }
+}
+
 pub fn get_input(message: &str) -> String {
     // Gets the users input about "message"
     let mut input = String::new();
@@ -52,3 +103,86 @@ pub fn get_input(message: &str) -> String {
         .expect("Failed to read line");
     return input;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> NaiveDateTime {
+        return NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap();
+    }
+
+    #[test]
+    fn string_to_bytes_value_reads_listing_sizes() {
+        assert_eq!(string_to_bytes_value("12345"), Some(12345.0));
+        assert_eq!(string_to_bytes_value(" 512 "), Some(512.0));
+        assert_eq!(string_to_bytes_value("1K"), Some(1024.0));
+        assert_eq!(string_to_bytes_value("12.5M"), Some(12.5 * 1048576.0));
+        assert_eq!(string_to_bytes_value("2g"), Some(2.0 * 1073741824.0));
+        assert_eq!(string_to_bytes_value("1T"), Some(1099511627776.0));
+    }
+
+    #[test]
+    fn string_to_bytes_value_has_no_size_for_directories() {
+        assert_eq!(string_to_bytes_value("-"), None);
+        assert_eq!(string_to_bytes_value(""), None);
+        assert_eq!(string_to_bytes_value("M"), None);
+    }
+
+    #[test]
+    fn size_tolerance_is_one_unit_of_the_listed_size() {
+        assert_eq!(size_tolerance(0), 1024);
+        assert_eq!(size_tolerance(1000), 1024);
+        assert_eq!(size_tolerance(1048575), 1024);
+        assert_eq!(size_tolerance(1048576), 1048576);
+        assert_eq!(size_tolerance(12 * 1048576), 1048576);
+        assert_eq!(size_tolerance(3 * 1073741824), 1073741824);
+        // Nothing is listed in a bigger unit than terabytes
+        assert_eq!(size_tolerance(u64::MAX), 1099511627776);
+    }
+
+    #[test]
+    fn date_range_includes_start_and_excludes_end() {
+        let range = DateRange {
+            since: Some(at("2024-01-05 00:00:00")),
+            until: Some(at("2024-01-06 00:00:00")),
+        };
+        assert!(!range.contains(at("2024-01-04 23:59:59")));
+        assert!(range.contains(at("2024-01-05 00:00:00")));
+        assert!(range.contains(at("2024-01-05 23:59:59")));
+        assert!(!range.contains(at("2024-01-06 00:00:00")));
+    }
+
+    #[test]
+    fn date_range_may_be_open_on_either_side() {
+        let everything = DateRange::default();
+        assert!(everything.contains(at("1970-01-01 00:00:00")));
+        assert!(everything.contains(at("2100-01-01 00:00:00")));
+
+        let since = DateRange {
+            since: Some(at("2024-01-05 00:00:00")),
+            until: None,
+        };
+        assert!(!since.contains(at("2024-01-04 00:00:00")));
+        assert!(since.contains(at("2100-01-01 00:00:00")));
+
+        let until = DateRange {
+            since: None,
+            until: Some(at("2024-01-05 00:00:00")),
+        };
+        assert!(until.contains(at("1970-01-01 00:00:00")));
+        assert!(!until.contains(at("2024-01-05 00:00:00")));
+    }
+
+    #[test]
+    fn date_range_keeps_directories_changed_after_the_end() {
+        let range = DateRange {
+            since: Some(at("2024-01-05 00:00:00")),
+            until: Some(at("2024-01-06 00:00:00")),
+        };
+        assert!(!range.may_contain_directory(at("2024-01-04 23:59:59")));
+        assert!(range.may_contain_directory(at("2024-01-05 00:00:00")));
+        // A directory changed later can still hold files from inside the range
+        assert!(range.may_contain_directory(at("2024-02-01 00:00:00")));
+    }
+}
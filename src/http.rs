@@ -0,0 +1,341 @@
+use std::io::Error;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use reqwest::{Proxy, StatusCode};
+
+use crate::progress;
+
+// Sent with every request so the mirror operators know who is fetching from them
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    "/",
+    env!("CARGO_PKG_VERSION"),
+    " (EDDN archive indexer)"
+);
+
+// How failed requests are retried. Used for every request the crawler and downloader make
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // Total number of tries, including the first one
+    pub max_attempts: u32,
+    // The delay before the first retry, doubled for every retry after that
+    pub base_delay: Duration,
+    // The longest we back off for, even when the server asks for longer with Retry-After
+    pub max_delay: Duration,
+}
+
+// Rate limiting and server errors are worth another try, anything else is the caller's problem
+fn is_retryable_status(status: StatusCode) -> bool {
+    return status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+}
+
+// Timeouts and connection problems are worth another try, a malformed url or redirect loop isn't
+fn is_retryable_error(error: &reqwest::Error) -> bool {
+    return error.is_timeout() || error.is_connect() || error.is_request() || error.is_body();
+}
+
+// Reads a Retry-After header, which is either a number of seconds or a http date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    return (date.with_timezone(&Utc) - Utc::now()).to_std().ok();
+}
+
+impl RetryPolicy {
+    // Exponential backoff with jitter: half of the delay is fixed and the other half is random,
+    // so workers that failed together don't all come back at the same moment
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        let half = delay / 2;
+        return half + half.mul_f64(rand::random::<f64>());
+    }
+
+    // Sends a request through `send` until it succeeds, fails for good or we run out of attempts.
+    // Responses with a status that isn't retryable (like a 404) are returned for the caller to deal with
+    pub fn send<F>(&self, url: &str, send: F) -> Result<Response, Error>
+    where
+        F: Fn() -> reqwest::Result<Response>,
+    {
+        let mut attempt = 1;
+        loop {
+            let (reason, delay) = match send() {
+                Ok(response) if is_retryable_status(response.status()) => (
+                    format!("status {}", response.status()),
+                    retry_after(&response),
+                ),
+                Ok(response) => return Ok(response),
+                Err(e) if is_retryable_error(&e) => (e.to_string(), None),
+                Err(e) => return Err(Error::other(format!("Request to {} failed: {}", url, e))),
+            };
+
+            if attempt >= self.max_attempts {
+                return Err(Error::other(format!(
+                    "Request to {} failed after {} attempts: {}",
+                    url, attempt, reason
+                )));
+            }
+            // The server knows best how long to wait, as long as that isn't longer than we would ever back off
+            let delay = match delay {
+                Some(asked) if asked > self.max_delay => {
+                    progress::println(format!(
+                        "{} asked to wait {:.0}s before retrying, waiting the maximum of {:.0}s instead",
+                        url,
+                        asked.as_secs_f64(),
+                        self.max_delay.as_secs_f64()
+                    ));
+                    self.max_delay
+                }
+                Some(asked) => asked,
+                None => self.backoff(attempt - 1),
+            };
+            progress::println(format!(
+                "Request to {} failed ({}), retrying in {:.1}s (attempt {}/{})",
+                url,
+                reason,
+                delay.as_secs_f64(),
+                attempt + 1,
+                self.max_attempts
+            ));
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+}
+
+// How the shared client talks to servers
+#[derive(Clone, Debug)]
+pub struct ClientSettings {
+    pub connect_timeout: Duration,
+    // How long to wait for the server to send anything, for the headers or the next chunk of a body
+    pub read_timeout: Duration,
+    pub user_agent: String,
+    // Sends every request through this proxy. Without one the usual HTTP_PROXY/HTTPS_PROXY variables are used
+    pub proxy: Option<String>,
+    // Extra "Name: Value" headers sent with every request
+    pub headers: Vec<String>,
+}
+
+// One client shared by everything that makes requests, so connections to the mirror are kept alive
+// and reused between listings and downloads. Every request goes through the retry policy
+pub struct HttpClient {
+    client: Client,
+    retry: RetryPolicy,
+}
+
+fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), Error> {
+    let invalid = || {
+        Error::other(format!(
+            "Invalid header '{}', expected 'Name: Value'",
+            header
+        ))
+    };
+    let (name, value) = header.split_once(':').ok_or_else(invalid)?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| invalid())?;
+    let value = HeaderValue::from_str(value.trim()).map_err(|_| invalid())?;
+    return Ok((name, value));
+}
+
+impl HttpClient {
+    pub fn new(settings: &ClientSettings, retry: RetryPolicy) -> Result<HttpClient, Error> {
+        let mut headers = HeaderMap::new();
+        for header in &settings.headers {
+            let (name, value) = parse_header(header)?;
+            headers.append(name, value);
+        }
+
+        let mut builder = Client::builder()
+            .connect_timeout(settings.connect_timeout)
+            // The blocking client applies this to every read, so it doesn't cut off long downloads that are still moving
+            .timeout(settings.read_timeout)
+            .tcp_keepalive(Duration::from_secs(60))
+            .user_agent(&settings.user_agent)
+            .default_headers(headers);
+        if let Some(proxy) = &settings.proxy {
+            builder = builder.proxy(Proxy::all(proxy).map_err(Error::other)?);
+        }
+        let client = builder.build().map_err(Error::other)?;
+        return Ok(HttpClient { client, retry });
+    }
+
+    // Sends the request built by `request` with the retry policy, see RetryPolicy::send
+    pub fn send<F>(&self, url: &str, request: F) -> Result<Response, Error>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        return self.retry.send(url, || request(&self.client).send());
+    }
+
+    // For work that can fail after the response came in, like reading its body
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        return &self.retry;
+    }
+
+    pub fn get(&self, url: &str) -> Result<Response, Error> {
+        return self.send(url, |client| client.get(url));
+    }
+
+    // Fetches a page and reads all of it. The connection can still drop while the body is coming in,
+    // that is retried like a failed request. Statuses that aren't a success are an error
+    pub fn get_text(&self, url: &str) -> Result<String, Error> {
+        let mut attempt = 1;
+        loop {
+            let response = self.get(url)?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(Error::other(format!(
+                    "Request to {} failed: {}",
+                    url, status
+                )));
+            }
+            let error = match response.text() {
+                Ok(body) => return Ok(body),
+                Err(e) => e,
+            };
+            if attempt >= self.retry.max_attempts {
+                return Err(Error::other(format!(
+                    "Reading {} failed after {} attempts: {}",
+                    url, attempt, error
+                )));
+            }
+            let delay = self.retry.backoff(attempt - 1);
+            progress::println(format!(
+                "Reading {} failed ({}), retrying in {:.1}s (attempt {}/{})",
+                url,
+                error,
+                delay.as_secs_f64(),
+                attempt + 1,
+                self.retry.max_attempts
+            ));
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::time::Instant;
+
+    fn response(status: u16, retry_after: Option<&str>) -> Response {
+        let mut builder = http::Response::builder().status(status);
+        if let Some(value) = retry_after {
+            builder = builder.header(RETRY_AFTER, value);
+        }
+        return Response::from(builder.body(String::new()).unwrap());
+    }
+
+    fn policy(base_delay: Duration, max_delay: Duration) -> RetryPolicy {
+        return RetryPolicy {
+            max_attempts: 3,
+            base_delay,
+            max_delay,
+        };
+    }
+
+    #[test]
+    fn retry_after_reads_seconds() {
+        assert_eq!(
+            retry_after(&response(429, Some("120"))),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            retry_after(&response(429, Some(" 0 "))),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after(&response(429, None)), None);
+        assert_eq!(retry_after(&response(429, Some("soon"))), None);
+        assert_eq!(retry_after(&response(429, Some("-5"))), None);
+    }
+
+    #[test]
+    fn retry_after_reads_http_dates() {
+        let later = (Utc::now() + chrono::TimeDelta::minutes(10)).to_rfc2822();
+        let delay = retry_after(&response(503, Some(&later))).unwrap();
+        assert!(delay > Duration::from_secs(590) && delay <= Duration::from_secs(600));
+        // A date that has already passed means there is nothing to wait for
+        let earlier = (Utc::now() - chrono::TimeDelta::minutes(10)).to_rfc2822();
+        assert_eq!(retry_after(&response(503, Some(&earlier))), None);
+    }
+
+    #[test]
+    fn backoff_doubles_from_the_base_delay() {
+        let retry = policy(Duration::from_secs(1), Duration::from_secs(60));
+        for (attempt, full) in [(0, 1), (1, 2), (2, 4), (5, 32)] {
+            let delay = retry.backoff(attempt);
+            let full = Duration::from_secs(full);
+            assert!(
+                delay >= full / 2 && delay <= full,
+                "{}: {:?}",
+                attempt,
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn backoff_stays_under_the_max_delay() {
+        let retry = policy(Duration::from_secs(1), Duration::from_secs(60));
+        for attempt in [6, 31, 32, 1000, u32::MAX] {
+            let delay = retry.backoff(attempt);
+            assert!(
+                delay >= Duration::from_secs(30) && delay <= Duration::from_secs(60),
+                "{}: {:?}",
+                attempt,
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn send_waits_at_most_the_max_delay_for_retry_after() {
+        let retry = policy(Duration::from_millis(1), Duration::from_millis(10));
+        let sent = Cell::new(0);
+        let started = Instant::now();
+        let result = retry.send("https://example.com/", || {
+            sent.set(sent.get() + 1);
+            if sent.get() == 1 {
+                return Ok(response(429, Some("86400")));
+            }
+            return Ok(response(200, None));
+        });
+        assert_eq!(result.unwrap().status(), StatusCode::OK);
+        assert_eq!(sent.get(), 2);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn send_gives_up_after_max_attempts() {
+        let retry = policy(Duration::from_millis(1), Duration::from_millis(10));
+        let sent = Cell::new(0);
+        let result = retry.send("https://example.com/", || {
+            sent.set(sent.get() + 1);
+            return Ok(response(503, None));
+        });
+        assert!(result.is_err());
+        assert_eq!(sent.get(), 3);
+    }
+
+    #[test]
+    fn send_returns_errors_that_are_not_retryable() {
+        let retry = policy(Duration::from_millis(1), Duration::from_millis(10));
+        let sent = Cell::new(0);
+        let result = retry.send("https://example.com/", || {
+            sent.set(sent.get() + 1);
+            return Ok(response(404, None));
+        });
+        assert_eq!(result.unwrap().status(), StatusCode::NOT_FOUND);
+        assert_eq!(sent.get(), 1);
+    }
+}
@@ -1,99 +1,603 @@
-use bzip2::read::BzDecoder;
-use mongodb::sync::Client;
+use bzip2::read::MultiBzDecoder;
+use chrono::Utc;
+use mongodb::bson::Document;
+use mongodb::error::ErrorKind as MongoErrorKind;
+use mongodb::sync::{Client, Collection};
 use rayon::prelude::*;
-use serde_json::Value;
-use std::fs::File;
-use std::io::{Error, Read};
-use std::path::Path;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-enum Reader {
-    DecompressorReader(BzDecoder<File>),
-    NormalReader(File),
+use crate::index::FileEntry;
+use crate::ledger::{Ledger, LedgerEntry};
+use crate::manifest::{FileStatus, Manifest};
+use crate::progress::{self, Progress, Unit};
+use crate::schema::Schema;
+
+// A batch is sent once its messages add up to this many bytes, even if it isn't full yet. MongoDB
+// takes up to 48MB in one message, this keeps well under that
+const MAX_BATCH_BYTES: usize = 16 * 1024 * 1024;
+
+// How much of a file is read at once
+const READ_BUFFER_SIZE: usize = 256 * 1024;
+
+// Where the lines we couldn't import are logged, inside of the downloads directory
+pub const REJECTS_DIR: &str = "rejects";
+
+// A file is given up on part way once this many lines show too many rejects, so a file that is
+// garbage throughout doesn't have to be read to the end first
+const MIN_LINES_FOR_REJECT_RATE: u64 = 1000;
+
+// What MongoDB answers when a document with the same _id is already there
+const DUPLICATE_KEY_ERROR: i32 = 11000;
+
+pub struct Importer<'a> {
+    pub client: &'a Client,
+    pub schema: &'a Schema,
+    pub download_dir: &'a Path,
+    // The most messages sent to the database in one insert
+    pub batch_size: usize,
+    // Share of a file's lines that may be rejected before the file is given up on
+    pub max_reject_rate: f64,
+    pub num_workers: usize,
+}
+
+// Where a file came from, as the manifest has it from its download
+struct Source {
+    url: String,
+    checksum: Option<String>,
+}
+
+// How importing one file went
+pub struct ImportedFile {
+    pub lines: u64,
+    pub inserted: u64,
+    // Messages that were already in the database, from an earlier import or another file
+    pub duplicates: u64,
+    pub rejected: u64,
+}
+
+pub enum ImportOutcome {
+    Imported(ImportedFile),
+    // The ledger says this copy of the file was imported before
+    AlreadyImported,
+    Failed(String),
+}
+
+// How each file of an import went
+pub struct ImportReport<'a> {
+    pub outcomes: Vec<(&'a FileEntry, ImportOutcome)>,
+}
+
+impl ImportReport<'_> {
+    fn imported_files(&self) -> impl Iterator<Item = &ImportedFile> {
+        return self
+            .outcomes
+            .iter()
+            .filter_map(|(_, outcome)| match outcome {
+                ImportOutcome::Imported(imported) => return Some(imported),
+                _ => return None,
+            });
+    }
+
+    pub fn imported(&self) -> usize {
+        return self.imported_files().count();
+    }
+
+    pub fn already_imported(&self) -> usize {
+        return self
+            .outcomes
+            .iter()
+            .filter(|(_, x)| matches!(x, ImportOutcome::AlreadyImported))
+            .count();
+    }
+
+    pub fn inserted(&self) -> u64 {
+        return self.imported_files().map(|x| x.inserted).sum();
+    }
+
+    pub fn duplicates(&self) -> u64 {
+        return self.imported_files().map(|x| x.duplicates).sum();
+    }
+
+    pub fn rejected(&self) -> u64 {
+        return self.imported_files().map(|x| x.rejected).sum();
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = (&FileEntry, &str)> {
+        return self
+            .outcomes
+            .iter()
+            .filter_map(|(file, outcome)| match outcome {
+                ImportOutcome::Failed(reason) => return Some((*file, reason.as_str())),
+                _ => return None,
+            });
+    }
+}
+
+impl ImportedFile {
+    fn add(&mut self, batch: InsertedBatch) {
+        self.inserted += batch.inserted;
+        self.duplicates += batch.duplicates;
+    }
+}
+
+// Logs the lines of a file that couldn't be imported, the log is only created once there is one
+struct RejectsLog {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+impl RejectsLog {
+    // Starts over from the log of an earlier import of the file, if there is one
+    fn new(path: PathBuf) -> Result<RejectsLog, Error> {
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        return Ok(RejectsLog { path, writer: None });
+    }
+
+    // One JSON object a line, with the line number, what was wrong and the line itself
+    fn log(&mut self, line_number: u64, error: &str, line: &[u8]) -> Result<(), Error> {
+        if self.writer.is_none() {
+            fs::create_dir_all(self.path.parent().unwrap())?;
+            self.writer = Some(BufWriter::new(File::create(&self.path)?));
+        }
+        let writer = self.writer.as_mut().unwrap();
+        let entry = json!({
+            "line": line_number,
+            "error": error,
+            "raw": String::from_utf8_lossy(line),
+        });
+        serde_json::to_writer(&mut *writer, &entry)?;
+        writer.write_all(b"\n")?;
+        return Ok(());
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        if let Some(mut writer) = self.writer {
+            writer.flush()?;
+        }
+        return Ok(());
+    }
+}
+
+// Feeds a JSON value to the hasher with its object keys sorted, so the same value always hashes the same
+// however its keys were ordered in the archive
+fn hash_canonical(hasher: &mut Sha256, value: &Value) {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            hasher.update(b"{");
+            for key in keys {
+                hasher.update(Value::from(key.as_str()).to_string());
+                hasher.update(b":");
+                hash_canonical(hasher, &map[key]);
+                hasher.update(b",");
+            }
+            hasher.update(b"}");
+        }
+        Value::Array(items) => {
+            hasher.update(b"[");
+            for item in items {
+                hash_canonical(hasher, item);
+                hasher.update(b",");
+            }
+            hasher.update(b"]");
+        }
+        scalar => hasher.update(scalar.to_string()),
+    }
+}
+
+// A key that is the same for every copy of a message, whichever file or mirror it came from: who uploaded it,
+// when the gateway got it and what it says
+fn message_key(json_blob: &Value) -> String {
+    let mut hasher = Sha256::new();
+    hash_canonical(&mut hasher, &json_blob["header"]["uploaderID"]);
+    hash_canonical(&mut hasher, &json_blob["header"]["gatewayTimestamp"]);
+    hash_canonical(&mut hasher, &json_blob["message"]);
+    return format!("{:x}", hasher.finalize());
+}
+
+// Turns one line of an archive into a document, or says what is wrong with it. Lines sent to the
+// schema's test endpoint come back as None
+fn parse_line(line: &[u8], schema: &Schema) -> Result<Option<Document>, String> {
+    let line = std::str::from_utf8(line).map_err(|e| format!("Invalid UTF-8: {}", e))?;
+    let json_blob: Value =
+        serde_json::from_str(line).map_err(|e| format!("Invalid JSON: {}", e))?;
+    // Skip anything that was sent to the schema's test endpoint
+    let schema_ref = json_blob["$schemaRef"].as_str().unwrap_or_default();
+    if !schema.accepts_message(schema_ref) {
+        return Ok(None);
+    }
+    let mut doc =
+        bson::to_document(&json_blob).map_err(|e| format!("Can't convert to BSON: {}", e))?;
+    // Keyed on the message so a message that is imported again is turned away instead of stored twice
+    doc.insert("_id", message_key(&json_blob));
+    return Ok(Some(doc));
 }
 
-fn get_reader(file_name: &str) -> Result<Reader, Error> {
-    let file = File::open(file_name)?;
-    if file_name.ends_with("bz2") {
-        return Ok(Reader::DecompressorReader(BzDecoder::new(
-            file,
+// Reads a file line by line, decompressing it on the fly if it is an archive
+fn open_lines<'a>(
+    file_path: &Path,
+    progress: &'a Progress,
+) -> Result<Box<dyn BufRead + 'a>, Error> {
+    // Progress is counted in bytes on disk, compressed or not
+    let file = progress.reader(File::open(file_path)?);
+    // Archives that were added to over the day are several bz2 streams, the lines go on in the next one
+    if file_path.extension().is_some_and(|ext| ext == "bz2") {
+        return Ok(Box::new(BufReader::with_capacity(
+            READ_BUFFER_SIZE,
+            MultiBzDecoder::new(file),
         )));
     }
-    return Ok(Reader::NormalReader(file));
-}
-
-pub fn import_files(
-    client: &Client,
-    file_paths: &Vec<String>,
-    num_workers: usize,
-) -> Result<(), Error> {
-    let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(num_workers)
-        .build()
-        .unwrap();
-    pool.install(|| {
-        file_paths.par_iter().for_each(|file_path| {
-            println!("Importing file: {}", file_path);
-            // Get the reader for the file and import it
-            let db = client.database("FSSSignalDiscovered");
-            let collection = db.collection("rust_test");
-
-            match get_reader(file_path) {
-                Ok(Reader::DecompressorReader(mut r)) => {
-                    // Compressed file reader
-                    // Collect all lines from the file and store them
-                    let mut str = String::new();
-                    r.read_to_string(&mut str).unwrap();
-                    let mut docs = Vec::new();
-                    for line in str.trim().split("\n") {
-                        let json_blob: Value = serde_json::from_str(&line)
-                            .expect(&format!("Error loading json data from: {}", file_path));
-                        let doc = bson::to_document(&json_blob)
-                            .expect("Error converting json blob to bson!");
-                        docs.push(doc);
-                    }
-                    
-                    // Remove the original string from memory
-                    drop(str);
-                    // Insert all lines into the collection
-                    let _ = collection.insert_many(docs).run().unwrap();
-
-                    // Move the file after processing
-                    let processed_file_path = format!("downloads/processed/{}", file_path.replace("downloads/", ""));
-                    // Ensure the processed directory exists
-                    if !Path::new("downloads/processed").exists() {
-                        std::fs::create_dir_all("downloads/processed").unwrap();
-                    }
-                    std::fs::rename(file_path, processed_file_path).expect("Error moving file after import!");
+    return Ok(Box::new(BufReader::with_capacity(READ_BUFFER_SIZE, file)));
+}
+
+// How a batch went into the database
+struct InsertedBatch {
+    inserted: u64,
+    duplicates: u64,
+}
+
+// Sends a batch to the database and empties it. Messages that are already there are counted as
+// duplicates, anything else that goes wrong fails the batch
+fn insert_batch(
+    collection: &Collection<Document>,
+    batch: &mut Vec<Document>,
+) -> Result<InsertedBatch, Error> {
+    let count = batch.len() as u64;
+    if count == 0 {
+        return Ok(InsertedBatch {
+            inserted: 0,
+            duplicates: 0,
+        });
+    }
+    // Unordered, so the rest of the batch still goes in after a duplicate
+    let result = collection.insert_many(batch.drain(..)).ordered(false).run();
+    let e = match result {
+        Ok(_) => {
+            return Ok(InsertedBatch {
+                inserted: count,
+                duplicates: 0,
+            })
+        }
+        Err(e) => e,
+    };
+    if let MongoErrorKind::InsertMany(failure) = &*e.kind {
+        let write_errors = failure.write_errors.as_deref().unwrap_or_default();
+        let only_duplicates = write_errors.iter().all(|x| x.code == DUPLICATE_KEY_ERROR);
+        if failure.write_concern_error.is_none() && only_duplicates {
+            let duplicates = write_errors.len() as u64;
+            return Ok(InsertedBatch {
+                inserted: count - duplicates,
+                duplicates,
+            });
+        }
+    }
+    return Err(Error::other(e));
+}
+
+impl Importer<'_> {
+    fn too_many_rejects(&self, imported: &ImportedFile) -> bool {
+        return imported.rejected as f64 > imported.lines as f64 * self.max_reject_rate;
+    }
+
+    fn move_to_processed(&self, file: &FileEntry) -> Result<(), Error> {
+        let processed_dir = self.download_dir.join("processed");
+        fs::create_dir_all(&processed_dir)?;
+        fs::rename(
+            self.download_dir.join(&file.name),
+            processed_dir.join(&file.name),
+        )?;
+        return Ok(());
+    }
+
+    // Streams a file into the database a batch at a time, so only one batch of it is ever held in memory.
+    // Lines that can't be imported are logged and skipped, unless there are too many of them. The file
+    // is recorded in the ledger once all of its messages are in
+    fn import_file(
+        &self,
+        file: &FileEntry,
+        source: Source,
+        ledger: &Ledger,
+        progress: &Progress,
+    ) -> Result<ImportedFile, Error> {
+        let started_at = Utc::now();
+        let file_path = self.download_dir.join(&file.name);
+        let size = fs::metadata(&file_path)?.len();
+        progress::println(format!("Importing file: {}", file_path.display()));
+        let db = self.client.database(&self.schema.database);
+        let collection = db.collection(&self.schema.collection);
+        let mut rejects = RejectsLog::new(
+            self.download_dir
+                .join(REJECTS_DIR)
+                .join(format!("{}.rejects", file.name)),
+        )?;
+
+        let mut imported = ImportedFile {
+            lines: 0,
+            inserted: 0,
+            duplicates: 0,
+            rejected: 0,
+        };
+        let mut reader = open_lines(&file_path, progress)?;
+        let mut line = Vec::new();
+        let mut line_number = 0;
+        let mut batch = Vec::with_capacity(self.batch_size);
+        let mut batch_bytes = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            line_number += 1;
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            imported.lines += 1;
+            match parse_line(&line, self.schema) {
+                Ok(Some(doc)) => {
+                    batch.push(doc);
+                    batch_bytes += line.len();
                 }
-                Ok(Reader::NormalReader(mut r)) => {
-                    // Normal file reader
-                    // Collect all lines from the file and store them
-                    let mut str = String::new();
-                    r.read_to_string(&mut str).unwrap();
-                    let mut docs = Vec::new();
-                    for line in str.trim().split("\n") {
-                        let json_blob: Value = serde_json::from_str(&line)
-                            .expect(&format!("Error loading json data from: {}", file_path));
-                        let doc = bson::to_document(&json_blob)
-                            .expect("Error converting json blob to bson!");
-                        docs.push(doc);
+                Ok(None) => {}
+                Err(e) => {
+                    imported.rejected += 1;
+                    rejects.log(line_number, &e, line.trim_ascii_end())?;
+                    if imported.lines >= MIN_LINES_FOR_REJECT_RATE
+                        && self.too_many_rejects(&imported)
+                    {
+                        break;
                     }
-                    // Remove the original string from memory
-                    drop(str);
-                    // Insert all lines into the collection
-                    let _ = collection.insert_many(docs).run().unwrap();
-
-                    // Move the file after processing
-                    let processed_file_path = format!("downloads/processed/{}", file_path.replace("downloads/", ""));
-                    // Ensure the processed directory exists
-                    if !Path::new("downloads/processed").exists() {
-                        std::fs::create_dir_all("downloads/processed").unwrap();
-                    }
-                    std::fs::rename(file_path, processed_file_path).expect("Error moving file after import!");
                 }
-                Err(e) => println!("Error when importing file: {}", e),
             }
-        })
-    });
-    Ok(())
+
+            if batch.len() >= self.batch_size || batch_bytes >= MAX_BATCH_BYTES {
+                imported.add(insert_batch(&collection, &mut batch)?);
+                batch_bytes = 0;
+            }
+        }
+        rejects.finish()?;
+        if self.too_many_rejects(&imported) {
+            return Err(Error::other(format!(
+                "Gave up after rejecting {} of {} lines, more than the allowed {}%",
+                imported.rejected,
+                imported.lines,
+                self.max_reject_rate * 100.0
+            )));
+        }
+        imported.add(insert_batch(&collection, &mut batch)?);
+
+        let finished_at = Utc::now();
+        ledger.record(&LedgerEntry {
+            file_name: file.name.clone(),
+            collection: self.schema.collection.clone(),
+            collection_uuid: ledger.collection_uuid()?,
+            url: source.url,
+            size,
+            checksum: source.checksum,
+            lines: imported.lines,
+            inserted: imported.inserted,
+            duplicates: imported.duplicates,
+            rejected: imported.rejected,
+            started_at,
+            finished_at,
+            duration_secs: (finished_at - started_at).as_seconds_f64(),
+        })?;
+        self.move_to_processed(file)?;
+        return Ok(imported);
+    }
+
+    pub fn import_files<'f>(&self, files: &[&'f FileEntry]) -> Result<ImportReport<'f>, Error> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.num_workers)
+            .build()
+            .unwrap();
+        let total_size = files
+            .iter()
+            .map(|file| {
+                fs::metadata(self.download_dir.join(&file.name)).map_or(0, |meta| meta.len())
+            })
+            .sum();
+        let manifest = Mutex::new(Manifest::load(self.download_dir)?);
+        let ledger = Ledger::new(self.client, self.schema);
+        let imported_before = ledger.load()?;
+        let progress = Progress::new("Import", Unit::Bytes, total_size, Some(files.len() as u64));
+        let outcomes = pool.install(|| {
+            files
+                .par_iter()
+                .map(|file| {
+                    let size = fs::metadata(self.download_dir.join(&file.name))
+                        .map_or(0, |meta| meta.len());
+                    let source = match manifest.lock().unwrap().files.get(&file.name) {
+                        Some(entry) => Source {
+                            url: entry.url.clone(),
+                            checksum: entry.checksum.clone(),
+                        },
+                        None => Source {
+                            url: file.url.clone(),
+                            checksum: None,
+                        },
+                    };
+                    let recorded = imported_before
+                        .get(&file.name)
+                        .filter(|entry| entry.matches(size, source.checksum.as_deref()));
+
+                    let outcome = match recorded {
+                        // Left behind by an import that stopped before it could move the file
+                        Some(_) => match self.move_to_processed(file) {
+                            Ok(()) => ImportOutcome::AlreadyImported,
+                            Err(e) => ImportOutcome::Failed(e.to_string()),
+                        },
+                        None => match self.import_file(file, source, &ledger, &progress) {
+                            Ok(imported) => ImportOutcome::Imported(imported),
+                            Err(e) => ImportOutcome::Failed(e.to_string()),
+                        },
+                    };
+                    let mut manifest = manifest.lock().unwrap();
+                    let entry = manifest.entry(file);
+                    match &outcome {
+                        ImportOutcome::Imported(imported) => {
+                            progress::println(format!(
+                                "Imported {} of {} messages from {}, {} were already imported and {} rejected",
+                                imported.inserted,
+                                imported.lines,
+                                file.name,
+                                imported.duplicates,
+                                imported.rejected
+                            ));
+                            entry.status = FileStatus::Imported;
+                            entry.imported_at = Some(Utc::now());
+                            entry.last_error = None;
+                            progress.finish_file(false);
+                        }
+                        ImportOutcome::AlreadyImported => {
+                            progress::println(format!(
+                                "Skipped {}, the import ledger has it imported already",
+                                file.name
+                            ));
+                            entry.status = FileStatus::Imported;
+                            entry.imported_at = recorded.map(|x| x.finished_at);
+                            entry.last_error = None;
+                            progress.advance(size);
+                            progress.finish_file(false);
+                        }
+                        ImportOutcome::Failed(reason) => {
+                            progress::eprintln(format!(
+                                "Error when importing {}: {}",
+                                file.name, reason
+                            ));
+                            entry.status = FileStatus::ImportFailed;
+                            entry.last_error = Some(reason.clone());
+                            progress.finish_file(true);
+                        }
+                    }
+                    // Saved after every file so a run that gets killed doesn't lose track of what it did
+                    if let Err(e) = manifest.save(self.download_dir) {
+                        progress::eprintln(format!("Could not save the manifest: {}", e));
+                    }
+                    return (*file, outcome);
+                })
+                .collect()
+        });
+        progress.finish();
+        manifest.into_inner().unwrap().save(self.download_dir)?;
+        return Ok(ImportReport { outcomes });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bzip2::write::BzEncoder;
+    use bzip2::Compression;
+
+    fn key(line: &str) -> String {
+        return message_key(&serde_json::from_str(line).unwrap());
+    }
+
+    const MESSAGE: &str = r#"{"$schemaRef":"https://eddn.edcd.io/schemas/fsssignaldiscovered/1","header":{"uploaderID":"abc","gatewayTimestamp":"2024-01-05T12:00:00.000Z","softwareName":"EDMC"},"message":{"SystemAddress":42,"StarSystem":"Sol","signals":[{"SignalName":"Station","IsStation":true},{"SignalName":"Carrier","IsStation":true}]}}"#;
+
+    #[test]
+    fn message_key_ignores_key_order() {
+        let reordered = r#"{"message":{"signals":[{"IsStation":true,"SignalName":"Station"},{"IsStation":true,"SignalName":"Carrier"}],"StarSystem":"Sol","SystemAddress":42},"header":{"softwareName":"EDMC","gatewayTimestamp":"2024-01-05T12:00:00.000Z","uploaderID":"abc"},"$schemaRef":"https://eddn.edcd.io/schemas/fsssignaldiscovered/1"}"#;
+        assert_eq!(key(MESSAGE), key(reordered));
+    }
+
+    #[test]
+    fn message_key_ignores_whitespace_and_other_header_fields() {
+        let other_copy = r#"{
+            "$schemaRef": "https://eddn.edcd.io/schemas/fsssignaldiscovered/1",
+            "header": {"uploaderID": "abc", "gatewayTimestamp": "2024-01-05T12:00:00.000Z", "softwareName": "EDDiscovery"},
+            "message": {"SystemAddress": 42, "StarSystem": "Sol", "signals": [{"SignalName": "Station", "IsStation": true}, {"SignalName": "Carrier", "IsStation": true}]}
+        }"#;
+        assert_eq!(key(MESSAGE), key(other_copy));
+    }
+
+    #[test]
+    fn message_key_tells_messages_apart() {
+        let original = key(MESSAGE);
+        for (from, to) in [
+            (r#""uploaderID":"abc""#, r#""uploaderID":"abd""#),
+            (r#"12:00:00.000Z"#, r#"12:00:01.000Z"#),
+            (r#""SystemAddress":42"#, r#""SystemAddress":43"#),
+            // The order of the items in an array is part of the message
+            (
+                r#"{"SignalName":"Station","IsStation":true},{"SignalName":"Carrier","IsStation":true}"#,
+                r#"{"SignalName":"Carrier","IsStation":true},{"SignalName":"Station","IsStation":true}"#,
+            ),
+            // A number and a string that read the same aren't the same
+            (r#""SystemAddress":42"#, r#""SystemAddress":"42""#),
+        ] {
+            assert!(MESSAGE.contains(from));
+            assert_ne!(
+                original,
+                key(&MESSAGE.replace(from, to)),
+                "{} -> {}",
+                from,
+                to
+            );
+        }
+    }
+
+    #[test]
+    fn message_key_does_not_mix_up_keys_and_values() {
+        // Moving text between a key and its value must not give the same hash input
+        assert_ne!(
+            key(r#"{"header":{},"message":{"ab":"c"}}"#),
+            key(r#"{"header":{},"message":{"a":"bc"}}"#)
+        );
+        assert_ne!(
+            key(r#"{"header":{},"message":{"a":["b","c"]}}"#),
+            key(r#"{"header":{},"message":{"a":["b,c"]}}"#)
+        );
+    }
+
+    #[test]
+    fn open_lines_reads_every_bz2_stream() {
+        let mut archive = Vec::new();
+        for line in ["{\"first\":1}\n", "{\"second\":2}\n"] {
+            let mut encoder = BzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(line.as_bytes()).unwrap();
+            archive.extend(encoder.finish().unwrap());
+        }
+        let path = std::env::temp_dir().join(format!(
+            "eddn_indexer_open_lines_{}.jsonl.bz2",
+            std::process::id()
+        ));
+        fs::write(&path, archive).unwrap();
+
+        let progress = Progress::new("Import", Unit::Bytes, 0, None);
+        let lines: Vec<String> = open_lines(&path, &progress)
+            .unwrap()
+            .lines()
+            .map(|line| line.unwrap())
+            .collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(lines, vec!["{\"first\":1}", "{\"second\":2}"]);
+    }
+
+    #[test]
+    fn parse_line_keys_documents_on_the_message() {
+        let schema = Schema::new("FSSSignalDiscovered");
+        let doc = parse_line(MESSAGE.as_bytes(), &schema).unwrap().unwrap();
+        assert_eq!(doc.get_str("_id").unwrap(), key(MESSAGE));
+    }
+
+    #[test]
+    fn parse_line_skips_test_messages_and_rejects_bad_lines() {
+        let schema = Schema::new("FSSSignalDiscovered");
+        let test_message = MESSAGE.replace("fsssignaldiscovered/1", "fsssignaldiscovered/1/test");
+        assert!(parse_line(test_message.as_bytes(), &schema)
+            .unwrap()
+            .is_none());
+        assert!(parse_line(b"{\"header\":", &schema).is_err());
+        assert!(parse_line(b"\xff\xfe", &schema).is_err());
+    }
 }
@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::downloader;
+use crate::helpers::{self, DateRange};
+use crate::listing;
+use crate::schema;
+
+// A file found during a crawl
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub name: String,
+    // The mime type if the server told us, otherwise just "File"
+    #[serde(rename = "type")]
+    pub file_type: String,
+    // Size in bytes. Most listings round this, so it can be a little off
+    pub size: u64,
+    // Listings don't say which timezone they are in, we take them as UTC
+    pub modified: DateTime<Utc>,
+    pub url: String,
+    // Decoded from the file name, e.g. "Journal.FSSSignalDiscovered" and 2024-01-05 for
+    // "Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2". Files that aren't EDDN archives have neither
+    pub schema: Option<String>,
+    pub date: Option<NaiveDate>,
+}
+
+// A directory seen during a crawl, its modified time tells us whether it has to be fetched again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DirectoryEntry {
+    pub url: String,
+    pub modified: DateTime<Utc>,
+}
+
+impl FileEntry {
+    pub fn new(
+        name: &str,
+        file_type: &str,
+        size: u64,
+        modified: NaiveDateTime,
+        url: &str,
+    ) -> FileEntry {
+        let archive = schema::split_archive_name(name);
+        return FileEntry {
+            name: name.to_string(),
+            file_type: file_type.to_string(),
+            size,
+            modified: modified.and_utc(),
+            url: url.to_string(),
+            schema: archive.map(|(schema, _)| schema.to_string()),
+            date: archive.map(|(_, date)| date),
+        };
+    }
+}
+
+// Every file directly inside a local directory, e.g. the downloads directory
+pub fn local_files(dir: &Path) -> Result<Vec<FileEntry>, Error> {
+    let url = reqwest::Url::from_directory_path(dir.canonicalize()?)
+        .map_err(|_| Error::other(format!("Can't list {}", dir.display())))?;
+    return Ok(listing::list_local_directory(url.as_str())?
+        .into_iter()
+        .filter(|entry| !entry.is_directory)
+        // Unfinished downloads aren't files we have yet
+        .filter(|entry| !downloader::is_partial_download(&entry.name))
+        .map(|entry| {
+            FileEntry::new(
+                &entry.name,
+                "File",
+                entry.size.unwrap_or_default() as u64,
+                entry.modified,
+                &entry.url,
+            )
+        })
+        .collect());
+}
+
+// Everything we learned from a crawl. Saved to disk so the next crawl only has to fetch what changed
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CrawlIndex {
+    #[serde(default)]
+    pub base_url: String,
+    #[serde(default)]
+    pub since: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub until: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub directories: Vec<DirectoryEntry>,
+    pub files: Vec<FileEntry>,
+}
+
+// The files.json saved by older versions of this tool, with listing sizes as floats and lighttpd timestamps
+#[derive(Deserialize)]
+struct LegacyIndex {
+    files: Vec<LegacyFileEntry>,
+}
+
+#[derive(Deserialize)]
+struct LegacyFileEntry {
+    name: String,
+    #[serde(rename = "type")]
+    file_type: String,
+    size: f64,
+    modified: String,
+    url: String,
+}
+
+impl LegacyIndex {
+    // Without the crawl's base url and dates nothing in it can stand in for a fresh crawl, but the
+    // files can still be downloaded and imported
+    fn into_index(self, path: &Path) -> Result<CrawlIndex, Error> {
+        let mut index = CrawlIndex::default();
+        for file in self.files {
+            let modified =
+                NaiveDateTime::parse_from_str(&file.modified, listing::LIGHTTPD_TIMESTAMP)
+                    .map_err(|e| {
+                        Error::other(format!(
+                        "{}: old index format with a bad modified time '{}' ({}), re-run `crawl`",
+                        path.display(),
+                        file.modified,
+                        e
+                    ))
+                    })?;
+            index.files.push(FileEntry::new(
+                &file.name,
+                &file.file_type,
+                file.size as u64,
+                modified,
+                &file.url,
+            ));
+        }
+        return Ok(index);
+    }
+}
+
+// Files that are new or different compared to a previous crawl
+pub struct IndexChanges<'a> {
+    pub added: Vec<&'a FileEntry>,
+    pub modified: Vec<&'a FileEntry>,
+}
+
+impl CrawlIndex {
+    pub fn new(base_url: &str, date_range: &DateRange) -> CrawlIndex {
+        return CrawlIndex {
+            base_url: base_url.to_string(),
+            since: date_range.since,
+            until: date_range.until,
+            directories: Vec::new(),
+            files: Vec::new(),
+        };
+    }
+
+    // Loads a saved index, including the files.json older versions of this tool saved
+    pub fn load(path: &Path) -> Result<CrawlIndex, Error> {
+        let contents = fs::read_to_string(path)?;
+        let error = match serde_json::from_str(&contents) {
+            Ok(index) => return Ok(index),
+            Err(e) => e,
+        };
+        match serde_json::from_str::<LegacyIndex>(&contents) {
+            Ok(legacy) => return legacy.into_index(path),
+            Err(_) => return Err(Error::other(format!("{}: {}", path.display(), error))),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        return helpers::save_json(path, self);
+    }
+
+    // The dates this index was crawled for
+    pub fn date_range(&self) -> DateRange {
+        return DateRange {
+            since: self.since,
+            until: self.until,
+        };
+    }
+
+    // Cached directories can only stand in for a fresh fetch if this index was crawled
+    // from the same place and covers every date the new crawl is interested in
+    pub fn can_reuse_for(&self, base_url: &str, date_range: &DateRange) -> bool {
+        let covers_start = match (self.since, date_range.since) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(ours), Some(theirs)) => ours <= theirs,
+        };
+        let covers_end = match (self.until, date_range.until) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(ours), Some(theirs)) => ours >= theirs,
+        };
+        return self.base_url == base_url && covers_start && covers_end;
+    }
+
+    pub fn find_directory(&self, url: &str) -> Option<&DirectoryEntry> {
+        return self.directories.iter().find(|dir| dir.url == url);
+    }
+
+    // Every file below `url` that falls within `date_range`, in the order they were crawled
+    pub fn files_under<'a>(
+        &'a self,
+        url: &'a str,
+        date_range: &'a DateRange,
+    ) -> impl Iterator<Item = &'a FileEntry> + 'a {
+        return self.files.iter().filter(move |file| {
+            file.url.starts_with(url) && date_range.contains(file.modified.naive_utc())
+        });
+    }
+
+    // Every directory below `url`, not including `url` itself
+    pub fn directories_under<'a>(
+        &'a self,
+        url: &'a str,
+    ) -> impl Iterator<Item = &'a DirectoryEntry> + 'a {
+        return self
+            .directories
+            .iter()
+            .filter(move |dir| dir.url != url && dir.url.starts_with(url));
+    }
+
+    // Compares this index to an older one, a file counts as modified when its size or modified time changed
+    pub fn changes_since<'a>(&'a self, previous: &CrawlIndex) -> IndexChanges<'a> {
+        let previous_files: HashMap<&str, &FileEntry> = previous
+            .files
+            .iter()
+            .map(|file| (file.url.as_str(), file))
+            .collect();
+
+        let mut changes = IndexChanges {
+            added: Vec::new(),
+            modified: Vec::new(),
+        };
+        for file in &self.files {
+            match previous_files.get(file.url.as_str()) {
+                None => changes.added.push(file),
+                Some(old) => {
+                    if old.modified != file.modified || old.size != file.size {
+                        changes.modified.push(file);
+                    }
+                }
+            }
+        }
+        return changes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE_URL: &str = "https://example.com/EDDN/";
+
+    fn at(date: &str) -> NaiveDateTime {
+        return NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap();
+    }
+
+    fn range(since: Option<&str>, until: Option<&str>) -> DateRange {
+        return DateRange {
+            since: since.map(at),
+            until: until.map(at),
+        };
+    }
+
+    fn file(name: &str, size: u64, modified: &str) -> FileEntry {
+        return FileEntry::new(
+            name,
+            "File",
+            size,
+            at(modified),
+            &format!("{}{}", BASE_URL, name),
+        );
+    }
+
+    fn index(files: Vec<FileEntry>) -> CrawlIndex {
+        let mut index = CrawlIndex::new(BASE_URL, &DateRange::default());
+        index.files = files;
+        return index;
+    }
+
+    fn names(files: &[&FileEntry]) -> Vec<String> {
+        return files.iter().map(|file| file.name.clone()).collect();
+    }
+
+    #[test]
+    fn can_reuse_for_a_narrower_range() {
+        let index = CrawlIndex::new(
+            BASE_URL,
+            &range(Some("2024-01-01 00:00:00"), Some("2024-02-01 00:00:00")),
+        );
+        assert!(index.can_reuse_for(
+            BASE_URL,
+            &range(Some("2024-01-01 00:00:00"), Some("2024-02-01 00:00:00"))
+        ));
+        assert!(index.can_reuse_for(
+            BASE_URL,
+            &range(Some("2024-01-10 00:00:00"), Some("2024-01-20 00:00:00"))
+        ));
+    }
+
+    #[test]
+    fn can_reuse_for_needs_the_whole_range() {
+        let index = CrawlIndex::new(
+            BASE_URL,
+            &range(Some("2024-01-01 00:00:00"), Some("2024-02-01 00:00:00")),
+        );
+        assert!(!index.can_reuse_for(
+            BASE_URL,
+            &range(Some("2023-12-31 00:00:00"), Some("2024-01-20 00:00:00"))
+        ));
+        assert!(!index.can_reuse_for(
+            BASE_URL,
+            &range(Some("2024-01-10 00:00:00"), Some("2024-02-02 00:00:00"))
+        ));
+        // An open end asks for more than any closed range holds
+        assert!(!index.can_reuse_for(BASE_URL, &range(Some("2024-01-10 00:00:00"), None)));
+        assert!(!index.can_reuse_for(BASE_URL, &range(None, Some("2024-01-20 00:00:00"))));
+    }
+
+    #[test]
+    fn can_reuse_for_an_open_index() {
+        let index = CrawlIndex::new(BASE_URL, &DateRange::default());
+        assert!(index.can_reuse_for(BASE_URL, &DateRange::default()));
+        assert!(index.can_reuse_for(
+            BASE_URL,
+            &range(Some("2024-01-10 00:00:00"), Some("2024-01-20 00:00:00"))
+        ));
+    }
+
+    #[test]
+    fn can_reuse_for_needs_the_same_base_url() {
+        let index = CrawlIndex::new(BASE_URL, &DateRange::default());
+        assert!(!index.can_reuse_for("https://mirror.example.com/EDDN/", &DateRange::default()));
+        // Older indexes didn't save where they were crawled from
+        assert!(!CrawlIndex::default().can_reuse_for(BASE_URL, &DateRange::default()));
+    }
+
+    #[test]
+    fn changes_since_finds_added_and_modified_files() {
+        let previous = index(vec![
+            file("same.jsonl.bz2", 100, "2024-01-05 00:00:00"),
+            file("grown.jsonl.bz2", 100, "2024-01-05 00:00:00"),
+            file("touched.jsonl.bz2", 100, "2024-01-05 00:00:00"),
+            file("removed.jsonl.bz2", 100, "2024-01-05 00:00:00"),
+        ]);
+        let current = index(vec![
+            file("same.jsonl.bz2", 100, "2024-01-05 00:00:00"),
+            file("grown.jsonl.bz2", 200, "2024-01-05 00:00:00"),
+            file("touched.jsonl.bz2", 100, "2024-01-06 00:00:00"),
+            file("new.jsonl.bz2", 100, "2024-01-06 00:00:00"),
+        ]);
+        let changes = current.changes_since(&previous);
+        assert_eq!(names(&changes.added), vec!["new.jsonl.bz2"]);
+        assert_eq!(
+            names(&changes.modified),
+            vec!["grown.jsonl.bz2", "touched.jsonl.bz2"]
+        );
+    }
+
+    #[test]
+    fn changes_since_an_empty_index_adds_everything() {
+        let current = index(vec![
+            file("a.jsonl.bz2", 100, "2024-01-05 00:00:00"),
+            file("b.jsonl.bz2", 100, "2024-01-05 00:00:00"),
+        ]);
+        let changes = current.changes_since(&CrawlIndex::default());
+        assert_eq!(names(&changes.added), vec!["a.jsonl.bz2", "b.jsonl.bz2"]);
+        assert!(changes.modified.is_empty());
+    }
+}
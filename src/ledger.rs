@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::io::Error;
+
+use bson::{doc, Binary};
+use chrono::{DateTime, Utc};
+use mongodb::sync::{Client, Collection, Database};
+use serde::{Deserialize, Serialize};
+
+use crate::schema::Schema;
+
+// Kept next to the schema's messages, in the same database
+const LEDGER_COLLECTION: &str = "imports";
+
+// The record of a file that was imported, written once all of its messages are in
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub file_name: String,
+    // The collection the file's messages went into, a file imported into one collection isn't in another
+    pub collection: String,
+    // The id MongoDB gave that collection. One that is dropped and made again gets a new id, which
+    // tells the records from before the drop apart
+    #[serde(default)]
+    pub collection_uuid: Option<Binary>,
+    // Where the file was downloaded from
+    pub url: String,
+    // Size on disk and SHA-256 of the file that was imported, to tell a newer download of it apart
+    pub size: u64,
+    pub checksum: Option<String>,
+    pub lines: u64,
+    pub inserted: u64,
+    pub duplicates: u64,
+    pub rejected: u64,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub started_at: DateTime<Utc>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub finished_at: DateTime<Utc>,
+    pub duration_secs: f64,
+}
+
+impl LedgerEntry {
+    // Whether this entry is for the same copy of the file. Without checksums to compare the size has to do
+    pub fn matches(&self, size: u64, checksum: Option<&str>) -> bool {
+        return self.size == size
+            && match (self.checksum.as_deref(), checksum) {
+                (Some(recorded), Some(checksum)) => recorded == checksum,
+                _ => true,
+            };
+    }
+}
+
+// The files imported into a schema's database, so we know what is in there even after the local
+// files are gone. There is one ledger per database, each record says which collection it is for
+pub struct Ledger {
+    database: Database,
+    // The collection messages are imported into
+    messages: String,
+    collection: Collection<LedgerEntry>,
+}
+
+impl Ledger {
+    pub fn new(client: &Client, schema: &Schema) -> Ledger {
+        let database = client.database(&schema.database);
+        return Ledger {
+            collection: database.collection(LEDGER_COLLECTION),
+            database,
+            messages: schema.collection.clone(),
+        };
+    }
+
+    // The id of the messages collection as it is now, None if it doesn't exist (yet)
+    pub fn collection_uuid(&self) -> Result<Option<Binary>, Error> {
+        let mut specs = self
+            .database
+            .list_collections()
+            .filter(doc! {"name": &self.messages})
+            .run()
+            .map_err(Error::other)?;
+        match specs.next() {
+            Some(spec) => return Ok(spec.map_err(Error::other)?.info.uuid),
+            None => return Ok(None),
+        }
+    }
+
+    // Every file imported into the collection as it is now, by file name. Whatever was recorded for the
+    // collection before it was dropped isn't in the database anymore. Those records are kept as history,
+    // they just don't count
+    pub fn load(&self) -> Result<HashMap<String, LedgerEntry>, Error> {
+        let Some(uuid) = self.collection_uuid()? else {
+            return Ok(HashMap::new());
+        };
+        let filter = doc! {"collection": &self.messages, "collection_uuid": uuid};
+        let cursor = self.collection.find(filter).run().map_err(Error::other)?;
+        let mut entries = HashMap::new();
+        for entry in cursor {
+            let entry = entry.map_err(Error::other)?;
+            entries.insert(entry.file_name.clone(), entry);
+        }
+        return Ok(entries);
+    }
+
+    // Records a file as imported, replacing the record of an earlier import of it
+    pub fn record(&self, entry: &LedgerEntry) -> Result<(), Error> {
+        self.collection
+            .replace_one(
+                doc! {"file_name": &entry.file_name, "collection": &entry.collection},
+                entry,
+            )
+            .upsert(true)
+            .run()
+            .map_err(Error::other)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(size: u64, checksum: Option<&str>) -> LedgerEntry {
+        return LedgerEntry {
+            file_name: "Commodity-2024-01-05.jsonl.bz2".to_string(),
+            collection: "commodity".to_string(),
+            collection_uuid: None,
+            url: "https://example.com/Commodity-2024-01-05.jsonl.bz2".to_string(),
+            size,
+            checksum: checksum.map(|checksum| checksum.to_string()),
+            lines: 10,
+            inserted: 10,
+            duplicates: 0,
+            rejected: 0,
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            duration_secs: 1.0,
+        };
+    }
+
+    #[test]
+    fn matches_compares_size_and_checksum() {
+        let recorded = entry(100, Some("abc"));
+        assert!(recorded.matches(100, Some("abc")));
+        assert!(!recorded.matches(100, Some("def")));
+        assert!(!recorded.matches(101, Some("abc")));
+    }
+
+    #[test]
+    fn matches_falls_back_to_the_size_without_checksums() {
+        assert!(entry(100, None).matches(100, Some("abc")));
+        assert!(entry(100, Some("abc")).matches(100, None));
+        assert!(entry(100, None).matches(100, None));
+        assert!(!entry(100, None).matches(101, None));
+    }
+
+    #[test]
+    fn entries_from_before_collection_uuids_still_load() {
+        let mut document = bson::to_document(&entry(100, None)).unwrap();
+        document.remove("collection_uuid");
+        let loaded: LedgerEntry = bson::from_document(document).unwrap();
+        assert_eq!(loaded.collection_uuid, None);
+        assert_eq!(loaded.size, 100);
+    }
+}
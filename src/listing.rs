@@ -0,0 +1,551 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::ValueEnum;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use reqwest::Url;
+use serde::Deserialize;
+use soup::prelude::*;
+
+use crate::helpers;
+
+// Characters that can't appear as-is in a path segment
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+// One row of a directory listing
+pub struct ListingEntry {
+    pub name: String,
+    pub url: String,
+    pub is_directory: bool,
+    // Only lighttpd tells us the mime type of a file
+    pub file_type: Option<String>,
+    pub size: Option<f64>,
+    pub modified: NaiveDateTime,
+}
+
+// Turns the body of a directory listing page into the entries it lists.
+// `url` is the address the page was fetched from and is used to resolve relative links
+pub trait ListingParser: Send + Sync {
+    fn parse(&self, body: &str, url: &str) -> Result<Vec<ListingEntry>, Error>;
+}
+
+// The directory listing formats we know how to read
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ListingFormat {
+    /// Work out the format from each page
+    Auto,
+    /// lighttpd mod_dirlisting, used by edgalaxydata.space
+    Lighttpd,
+    /// nginx autoindex with the default html output
+    Nginx,
+    /// nginx autoindex with `autoindex_format json`
+    NginxJson,
+    /// Apache mod_autoindex, either as a table or with FancyIndexing
+    Apache,
+}
+
+impl ListingFormat {
+    pub fn parser(&self) -> Box<dyn ListingParser> {
+        match self {
+            ListingFormat::Auto => return Box::new(AutoDetectParser),
+            ListingFormat::Lighttpd => return Box::new(LighttpdParser),
+            ListingFormat::Nginx => return Box::new(NginxParser),
+            ListingFormat::NginxJson => return Box::new(NginxJsonParser),
+            ListingFormat::Apache => return Box::new(ApacheParser),
+        }
+    }
+}
+
+fn invalid_listing(url: &str, reason: &str) -> Error {
+    return Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid directory listing at {}: {}", url, reason),
+    );
+}
+
+// Resolves a link in a listing against the page url and works out the entry's name from it.
+// Link texts get cut short by nginx and apache so the name always comes from the href
+fn resolve_link(url: &str, href: &str) -> Result<(String, String), Error> {
+    let base = Url::parse(url).map_err(|e| invalid_listing(url, &e.to_string()))?;
+    let resolved = base
+        .join(href)
+        .map_err(|e| invalid_listing(url, &e.to_string()))?;
+    let name = resolved
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+        .unwrap_or_default();
+    return Ok((resolved.to_string(), name));
+}
+
+// Links back up the tree, to the server root or to sort the listing are not entries
+fn is_navigation_link(href: &str, text: &str) -> bool {
+    return href.starts_with("../")
+        || href.starts_with('?')
+        || href.starts_with('/')
+        || text.trim() == "Parent Directory";
+}
+
+// How lighttpd shows modified times, e.g. "2024-Jan-05 12:00:00"
+pub const LIGHTTPD_TIMESTAMP: &str = "%Y-%b-%d %H:%M:%S";
+
+fn parse_timestamp(value: &str, format: &str, url: &str) -> Result<NaiveDateTime, Error> {
+    return NaiveDateTime::parse_from_str(value.trim(), format).map_err(|e| {
+        invalid_listing(url, &format!("bad modified time '{}': {}", value.trim(), e))
+    });
+}
+
+// Parses `<pre>` style listings where every entry is a link followed by its modified time and size on the same line
+fn parse_pre_listing(
+    body: &str,
+    url: &str,
+    timestamp_format: &str,
+) -> Result<Vec<ListingEntry>, Error> {
+    let soup = Soup::new(body);
+    let pre = soup
+        .tag("pre")
+        .find()
+        .ok_or_else(|| invalid_listing(url, "no <pre> block found"))?;
+
+    let mut entries = Vec::new();
+    let mut current_link: Option<(String, String)> = None;
+    for child in pre.children() {
+        if child.is_element() && child.name() == "a" {
+            current_link = child.get("href").map(|href| (href, child.text()));
+            continue;
+        }
+        if !child.is_text() {
+            continue;
+        }
+        let Some((href, text)) = current_link.take() else {
+            continue;
+        };
+        if is_navigation_link(&href, &text) {
+            continue;
+        }
+
+        // The text after a link looks like "05-Jan-2024 12:00    12345" for nginx and "2024-01-05 12:00  12M" for apache
+        let details = child.text();
+        let columns: Vec<&str> = details.split_whitespace().collect();
+        if columns.len() < 3 {
+            return Err(invalid_listing(
+                url,
+                &format!("missing details for '{}'", href),
+            ));
+        }
+        let modified = parse_timestamp(
+            &format!("{} {}", columns[0], columns[1]),
+            timestamp_format,
+            url,
+        )?;
+        let (entry_url, name) = resolve_link(url, &href)?;
+        entries.push(ListingEntry {
+            name,
+            url: entry_url,
+            is_directory: href.ends_with('/'),
+            file_type: None,
+            size: helpers::string_to_bytes_value(columns[2]),
+            modified,
+        });
+    }
+    return Ok(entries);
+}
+
+// lighttpd mod_dirlisting, a table where every cell has a class: n(ame), m(odified), s(ize) and t(ype)
+pub struct LighttpdParser;
+
+impl ListingParser for LighttpdParser {
+    fn parse(&self, body: &str, url: &str) -> Result<Vec<ListingEntry>, Error> {
+        let soup = Soup::new(body);
+        if soup.class("n").find().is_none() {
+            return Err(invalid_listing(url, "no lighttpd name cells found"));
+        }
+
+        let mut entries = Vec::new();
+        for tr in soup.tag("tr").find_all() {
+            // Get the info about the file, rows without all four cells aren't entries
+            let (Some(file_name), Some(file_type), Some(file_size), Some(file_modified)) = (
+                tr.tag("td").class("n").find(),
+                tr.tag("td").class("t").find(),
+                tr.tag("td").class("s").find(),
+                tr.tag("td").class("m").find(),
+            ) else {
+                continue;
+            };
+            let Some(href) = file_name.tag("a").find().and_then(|a| a.get("href")) else {
+                continue;
+            };
+
+            // Skip "parent directory" items
+            if is_navigation_link(&href, &file_name.text()) {
+                continue;
+            }
+
+            let modified = parse_timestamp(&file_modified.text(), LIGHTTPD_TIMESTAMP, url)?;
+            let (entry_url, name) = resolve_link(url, &href)?;
+            let is_directory = file_type.text() == "Directory";
+            entries.push(ListingEntry {
+                name,
+                url: entry_url,
+                is_directory,
+                file_type: if is_directory {
+                    None
+                } else {
+                    Some(file_type.text())
+                },
+                size: helpers::string_to_bytes_value(&file_size.text()),
+                modified,
+            });
+        }
+        return Ok(entries);
+    }
+}
+
+// nginx autoindex, a <pre> block with "05-Jan-2024 12:00" timestamps
+pub struct NginxParser;
+
+impl ListingParser for NginxParser {
+    fn parse(&self, body: &str, url: &str) -> Result<Vec<ListingEntry>, Error> {
+        return parse_pre_listing(body, url, "%d-%b-%Y %H:%M");
+    }
+}
+
+#[derive(Deserialize)]
+struct NginxJsonEntry {
+    name: String,
+    #[serde(rename = "type")]
+    entry_type: String,
+    mtime: String,
+    size: Option<u64>,
+}
+
+// nginx autoindex with `autoindex_format json`
+pub struct NginxJsonParser;
+
+impl ListingParser for NginxJsonParser {
+    fn parse(&self, body: &str, url: &str) -> Result<Vec<ListingEntry>, Error> {
+        let rows: Vec<NginxJsonEntry> =
+            serde_json::from_str(body).map_err(|e| invalid_listing(url, &e.to_string()))?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let is_directory = row.entry_type == "directory";
+            // The names aren't escaped in the json output, so they have to be before being joined onto the url
+            let mut href = utf8_percent_encode(&row.name, PATH_SEGMENT).to_string();
+            if is_directory {
+                href.push('/');
+            }
+            let modified = DateTime::parse_from_rfc2822(&row.mtime)
+                .map_err(|e| {
+                    invalid_listing(url, &format!("bad modified time '{}': {}", row.mtime, e))
+                })?
+                .naive_utc();
+            let (entry_url, _) = resolve_link(url, &href)?;
+            entries.push(ListingEntry {
+                name: row.name,
+                url: entry_url,
+                is_directory,
+                file_type: None,
+                size: row.size.map(|size| size as f64),
+                modified,
+            });
+        }
+        return Ok(entries);
+    }
+}
+
+// Apache mod_autoindex, either the HTMLTable layout or the FancyIndexing <pre> layout
+pub struct ApacheParser;
+
+impl ListingParser for ApacheParser {
+    fn parse(&self, body: &str, url: &str) -> Result<Vec<ListingEntry>, Error> {
+        let soup = Soup::new(body);
+        if soup.tag("table").find().is_none() {
+            return parse_pre_listing(body, url, "%Y-%m-%d %H:%M");
+        }
+
+        let mut entries = Vec::new();
+        for tr in soup.tag("tr").find_all() {
+            let Some(link) = tr.tag("td").find_all().find_map(|td| td.tag("a").find()) else {
+                continue;
+            };
+            let Some(href) = link.get("href") else {
+                continue;
+            };
+            if is_navigation_link(&href, &link.text()) {
+                continue;
+            }
+
+            // The modified time is the first cell that reads as one, the size is the cell after it
+            let cells: Vec<String> = tr.tag("td").find_all().map(|td| td.text()).collect();
+            let Some(position) = cells.iter().position(|cell| {
+                NaiveDateTime::parse_from_str(cell.trim(), "%Y-%m-%d %H:%M").is_ok()
+            }) else {
+                return Err(invalid_listing(
+                    url,
+                    &format!("no modified time for '{}'", href),
+                ));
+            };
+            let modified = parse_timestamp(&cells[position], "%Y-%m-%d %H:%M", url)?;
+            let size = cells
+                .get(position + 1)
+                .and_then(|cell| helpers::string_to_bytes_value(cell.trim()));
+            let (entry_url, name) = resolve_link(url, &href)?;
+            entries.push(ListingEntry {
+                name,
+                url: entry_url,
+                is_directory: href.ends_with('/'),
+                file_type: None,
+                size,
+                modified,
+            });
+        }
+        return Ok(entries);
+    }
+}
+
+// Picks one of the other parsers by looking at the page
+pub struct AutoDetectParser;
+
+impl ListingParser for AutoDetectParser {
+    fn parse(&self, body: &str, url: &str) -> Result<Vec<ListingEntry>, Error> {
+        let format = if body.trim_start().starts_with('[') {
+            ListingFormat::NginxJson
+        } else if body.contains("class=\"n\"") {
+            ListingFormat::Lighttpd
+        } else if body.contains("?C=N;O=") || body.contains("<table") {
+            ListingFormat::Apache
+        } else if body.contains("<pre>") {
+            ListingFormat::Nginx
+        } else {
+            return Err(invalid_listing(url, "unrecognised listing format"));
+        };
+        return format.parser().parse(body, url);
+    }
+}
+
+// Lists a `file://` directory straight from the filesystem, for archives kept on local disk or a mounted share
+pub fn list_local_directory(url: &str) -> Result<Vec<ListingEntry>, Error> {
+    let path = Url::parse(url)
+        .ok()
+        .and_then(|parsed| parsed.to_file_path().ok())
+        .ok_or_else(|| invalid_listing(url, "not a local file url"))?;
+
+    let mut entries = Vec::new();
+    for dir_entry in fs::read_dir(&path)? {
+        let dir_entry = dir_entry?;
+        let metadata = dir_entry.metadata()?;
+        let is_directory = metadata.is_dir();
+        let entry_url = if is_directory {
+            Url::from_directory_path(dir_entry.path())
+        } else {
+            Url::from_file_path(dir_entry.path())
+        }
+        .map_err(|_| invalid_listing(url, "path can't be turned into a url"))?;
+        entries.push(ListingEntry {
+            name: dir_entry.file_name().to_string_lossy().to_string(),
+            url: entry_url.to_string(),
+            is_directory,
+            file_type: None,
+            size: if is_directory {
+                None
+            } else {
+                Some(metadata.len() as f64)
+            },
+            modified: DateTime::<Utc>::from(metadata.modified()?).naive_utc(),
+        });
+    }
+    // read_dir makes no promises about order, sort by name like a web server would
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    return Ok(entries);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    const URL: &str = "https://example.com/EDDN/";
+
+    const LIGHTTPD: &str = r#"<html><body><h2>Index of /EDDN/</h2>
+<div class="list"><table summary="Directory Listing" cellpadding="0" cellspacing="0">
+<thead><tr><th class="n">Name</th><th class="m">Last Modified</th><th class="s">Size</th><th class="t">Type</th></tr></thead>
+<tbody>
+<tr class="d"><td class="n"><a href="../">Parent Directory</a>/</td><td class="m">&nbsp;</td><td class="s">- &nbsp;</td><td class="t">Directory</td></tr>
+<tr class="d"><td class="n"><a href="2024/">2024</a>/</td><td class="m">2024-Jan-05 12:00:00</td><td class="s">- &nbsp;</td><td class="t">Directory</td></tr>
+<tr><td class="n"><a href="Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2">Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2</a></td><td class="m">2024-Jan-06 00:10:00</td><td class="s">12.5M</td><td class="t">application/x-bzip2</td></tr>
+</tbody></table></div></body></html>"#;
+
+    const NGINX: &str = r#"<html>
+<head><title>Index of /EDDN/</title></head>
+<body>
+<h1>Index of /EDDN/</h1><hr><pre><a href="../">../</a>
+<a href="2024/">2024/</a>                                              05-Jan-2024 12:00                   -
+<a href="Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2">Journal.FSSSignalDiscovered-2024-01-05.jsonl.b..&gt;</a> 06-Jan-2024 00:10            13107200
+<a href="Old%20Files.txt">Old Files.txt</a>                                      07-Feb-2024 08:30                  42
+</pre><hr></body>
+</html>"#;
+
+    const NGINX_JSON: &str = r#"
+[
+{ "name":"2024", "type":"directory", "mtime":"Fri, 05 Jan 2024 12:00:00 GMT" },
+{ "name":"Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2", "type":"file", "mtime":"Sat, 06 Jan 2024 00:10:00 GMT", "size":13107200 },
+{ "name":"Old #1.txt", "type":"file", "mtime":"Wed, 07 Feb 2024 08:30:00 GMT", "size":42 }
+]"#;
+
+    const APACHE_TABLE: &str = r#"<html><body><h1>Index of /EDDN</h1>
+<table>
+<tr><th valign="top"><img src="/icons/blank.gif" alt="[ICO]"></th><th><a href="?C=N;O=D">Name</a></th><th><a href="?C=M;O=A">Last modified</a></th><th><a href="?C=S;O=A">Size</a></th><th><a href="?C=D;O=A">Description</a></th></tr>
+<tr><th colspan="5"><hr></th></tr>
+<tr><td valign="top"><img src="/icons/back.gif" alt="[PARENTDIR]"></td><td><a href="/">Parent Directory</a></td><td>&nbsp;</td><td align="right">  - </td><td>&nbsp;</td></tr>
+<tr><td valign="top"><img src="/icons/folder.gif" alt="[DIR]"></td><td><a href="2024/">2024/</a></td><td align="right">2024-01-05 12:00  </td><td align="right">  - </td><td>&nbsp;</td></tr>
+<tr><td valign="top"><img src="/icons/unknown.gif" alt="[   ]"></td><td><a href="Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2">Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2</a></td><td align="right">2024-01-06 00:10  </td><td align="right"> 12M</td><td>&nbsp;</td></tr>
+<tr><th colspan="5"><hr></th></tr>
+</table>
+</body></html>"#;
+
+    const APACHE_FANCY: &str = r#"<html><body><h1>Index of /EDDN</h1>
+<pre><img src="/icons/blank.gif" alt="Icon "> <a href="?C=N;O=D">Name</a>                    <a href="?C=M;O=A">Last modified</a>      <a href="?C=S;O=A">Size</a>  <a href="?C=D;O=A">Description</a><hr><img src="/icons/back.gif" alt="[PARENTDIR]"> <a href="/">Parent Directory</a>                             -
+<img src="/icons/folder.gif" alt="[DIR]"> <a href="2024/">2024/</a>                   2024-01-05 12:00    -
+<img src="/icons/unknown.gif" alt="[   ]"> <a href="Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2">Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2</a> 2024-01-06 00:10   12M
+<hr></pre>
+</body></html>"#;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        return NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap();
+    }
+
+    fn parse(format: ListingFormat, body: &str) -> Vec<ListingEntry> {
+        return format.parser().parse(body, URL).unwrap();
+    }
+
+    // Every format lists the same directory and archive, only the file details differ
+    fn assert_directory_and_archive(entries: &[ListingEntry], archive_modified: NaiveDateTime) {
+        let directory = &entries[0];
+        assert_eq!(directory.name, "2024");
+        assert_eq!(directory.url, "https://example.com/EDDN/2024/");
+        assert!(directory.is_directory);
+        assert_eq!(directory.size, None);
+
+        let archive = &entries[1];
+        assert_eq!(
+            archive.name,
+            "Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2"
+        );
+        assert_eq!(
+            archive.url,
+            "https://example.com/EDDN/Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2"
+        );
+        assert!(!archive.is_directory);
+        assert_eq!(archive.modified, archive_modified);
+    }
+
+    #[test]
+    fn lighttpd_listing() {
+        let entries = parse(ListingFormat::Lighttpd, LIGHTTPD);
+        assert_eq!(entries.len(), 2);
+        assert_directory_and_archive(&entries, at(2024, 1, 6, 0, 10));
+        assert_eq!(entries[0].modified, at(2024, 1, 5, 12, 0));
+        assert_eq!(entries[0].file_type, None);
+        assert_eq!(entries[1].file_type.as_deref(), Some("application/x-bzip2"));
+        assert_eq!(entries[1].size, Some(12.5 * 1048576.0));
+    }
+
+    #[test]
+    fn lighttpd_rejects_other_listings() {
+        assert!(LighttpdParser.parse(NGINX, URL).is_err());
+    }
+
+    #[test]
+    fn nginx_listing() {
+        let entries = parse(ListingFormat::Nginx, NGINX);
+        assert_eq!(entries.len(), 3);
+        assert_directory_and_archive(&entries, at(2024, 1, 6, 0, 10));
+        assert_eq!(entries[1].size, Some(13107200.0));
+        // Names come from the href, not the shortened link text
+        assert_eq!(entries[2].name, "Old Files.txt");
+        assert_eq!(entries[2].url, "https://example.com/EDDN/Old%20Files.txt");
+        assert_eq!(entries[2].size, Some(42.0));
+    }
+
+    #[test]
+    fn nginx_rejects_bad_timestamps() {
+        let body = NGINX.replace("06-Jan-2024", "2024-01-06");
+        assert!(NginxParser.parse(&body, URL).is_err());
+    }
+
+    #[test]
+    fn nginx_json_listing() {
+        let entries = parse(ListingFormat::NginxJson, NGINX_JSON);
+        assert_eq!(entries.len(), 3);
+        assert_directory_and_archive(&entries, at(2024, 1, 6, 0, 10));
+        assert_eq!(entries[1].size, Some(13107200.0));
+        // Names aren't escaped in the json, the url has to be
+        assert_eq!(entries[2].name, "Old #1.txt");
+        assert_eq!(entries[2].url, "https://example.com/EDDN/Old%20%231.txt");
+    }
+
+    #[test]
+    fn nginx_json_rejects_html() {
+        assert!(NginxJsonParser.parse(NGINX, URL).is_err());
+    }
+
+    #[test]
+    fn apache_table_listing() {
+        let entries = parse(ListingFormat::Apache, APACHE_TABLE);
+        assert_eq!(entries.len(), 2);
+        assert_directory_and_archive(&entries, at(2024, 1, 6, 0, 10));
+        assert_eq!(entries[1].size, Some(12.0 * 1048576.0));
+    }
+
+    #[test]
+    fn apache_fancy_listing() {
+        let entries = parse(ListingFormat::Apache, APACHE_FANCY);
+        assert_eq!(entries.len(), 2);
+        assert_directory_and_archive(&entries, at(2024, 1, 6, 0, 10));
+        assert_eq!(entries[1].size, Some(12.0 * 1048576.0));
+    }
+
+    #[test]
+    fn auto_detect_picks_the_right_parser() {
+        // Each page should come out the way its own parser reads it
+        let lighttpd = parse(ListingFormat::Auto, LIGHTTPD);
+        assert_eq!(lighttpd.len(), 2);
+        assert!(lighttpd[1].file_type.is_some());
+
+        let nginx = parse(ListingFormat::Auto, NGINX);
+        assert_eq!(nginx.len(), 3);
+        assert_eq!(nginx[2].name, "Old Files.txt");
+
+        let nginx_json = parse(ListingFormat::Auto, NGINX_JSON);
+        assert_eq!(nginx_json.len(), 3);
+        assert_eq!(nginx_json[2].name, "Old #1.txt");
+
+        for body in [APACHE_TABLE, APACHE_FANCY] {
+            let apache = parse(ListingFormat::Auto, body);
+            assert_eq!(apache.len(), 2);
+            assert_eq!(apache[1].size, Some(12.0 * 1048576.0));
+        }
+    }
+
+    #[test]
+    fn auto_detect_rejects_unknown_pages() {
+        let body = "<html><body><p>Nothing to see here</p></body></html>";
+        assert!(AutoDetectParser.parse(body, URL).is_err());
+    }
+}
@@ -1,333 +1,456 @@
+// Explicit returns are the house style in this crate
+#![allow(clippy::needless_return)]
+
+mod cli;
+mod config;
+mod crawler;
 mod downloader;
+mod dumper;
 mod helpers;
+mod http;
 mod importer;
+mod index;
+mod ledger;
+mod listing;
+mod manifest;
+mod progress;
+mod schema;
+mod throttle;
+
+use std::path::Path;
+
+use clap::Parser;
+use mongodb::sync::Client;
+
+use cli::{
+    AllArgs, Cli, Command, CrawlArgs, DatabaseArgs, DownloadArgs, DownloadOptions, DumpArgs,
+    HttpArgs, ImportArgs, ImportOptions, SchemaArgs, SourceArgs, StatusArgs,
+};
+use crawler::Crawler;
+use helpers::DateRange;
+use http::HttpClient;
+use importer::Importer;
+use index::{CrawlIndex, FileEntry};
+use manifest::{FileStatus, Manifest};
+use schema::Schema;
+use throttle::RateLimiter;
+
+// Loads the index saved by an earlier crawl so the archive doesn't have to be crawled again
+fn load_index(path: &Path) -> std::io::Result<CrawlIndex> {
+    println!("Loading file index from {}...", path.display());
+    let index = CrawlIndex::load(path)?;
+    println!("Loaded {} file blobs.", index.files.len());
+    return Ok(index);
+}
 
-use std::fs;
-use std::rc::Rc;
-
-use bson::{doc, Document};
-use chrono::prelude::*;
-use html5ever::rcdom::Node;
-use mongodb::{options::FindOptions, sync::Client, sync::Collection};
-use num_cpus;
-use reqwest;
-use serde_json::{json, to_writer_pretty, Value};
-use soup::prelude::*;
-
-fn parse_url(td: &Rc<Node>, base_url: &str) -> Option<String> {
-    if td.text() != "None" {
-        let a = td.tag("a").find().expect("None");
-        if a.text() != "None" {
-            let url = a.get("href").expect("None");
-            if url != "None" {
-                return Some(format!("{}{}", base_url, url));
+// Crawls the archive, reusing whatever is still valid from the saved index, and saves the new index over it.
+// A crawl of fewer dates than the saved index leaves it alone, so the next full run can still reuse it
+fn crawl_stage(source: &SourceArgs, http: &HttpClient) -> std::io::Result<CrawlIndex> {
+    let previous = if source.full_crawl || !source.crawl_index.exists() {
+        None
+    } else {
+        match CrawlIndex::load(&source.crawl_index) {
+            Ok(index) => Some(index),
+            Err(e) => {
+                println!(
+                    "Could not load the saved crawl index, crawling everything: {}",
+                    e
+                );
+                None
             }
         }
+    };
+
+    // Start crawling the directories
+    let parser = source.listing_format.parser();
+    let request_limit = (source.crawl_rate > 0.0).then(|| RateLimiter::new(source.crawl_rate));
+    let crawler = Crawler {
+        date_range: source.date_range(),
+        previous: previous.as_ref(),
+        recrawl_newest: source.recrawl_newest,
+        parser: parser.as_ref(),
+        http,
+        request_limit: request_limit.as_ref(),
+        num_workers: source.crawl_workers.max(1),
+    };
+    let index = crawler.crawl(&crawler::root_url(&source.url)?)?;
+
+    if let Some(previous) = &previous {
+        let changes = index.changes_since(previous);
+        println!(
+            "{} files were added and {} files were modified since the last crawl",
+            changes.added.len(),
+            changes.modified.len()
+        );
+        changes
+            .added
+            .iter()
+            .for_each(|x| println!("Added: {}", x.name));
+        changes
+            .modified
+            .iter()
+            .for_each(|x| println!("Modified: {}", x.name));
+    }
+
+    let narrower = previous.as_ref().is_some_and(|prev| {
+        prev.base_url == index.base_url && !index.can_reuse_for(&prev.base_url, &prev.date_range())
+    });
+    if narrower {
+        println!(
+            "Not saving the crawl index, {} covers more dates than this crawl. Pass --full-crawl to replace it.",
+            source.crawl_index.display()
+        );
+        return Ok(index);
     }
-    return None;
+    index.save(&source.crawl_index)?;
+    println!(
+        "Saved {} file blobs to {}.",
+        index.files.len(),
+        source.crawl_index.display()
+    );
+    return Ok(index);
 }
 
-// Collects two lists: a list of files in the current directory and a list of directories
-fn find_files(url: &str) -> (Vec<Value>, Vec<String>) {
-    // Basically a const but I cannot figure out a better way to do this...
-    let update_17 = NaiveDate::from_ymd_opt(2023, 10, 15).unwrap();
-
-    let res = reqwest::blocking::get(url).unwrap();
-    let html = res.text().unwrap();
-    let soup = Soup::new(&html);
-
-    // Find all table rows and initialize return values
-    let trs = soup.tag("tr").find_all();
-    let mut files = Vec::new();
-    let mut directories = Vec::new();
-
-    for tr in trs {
-        // Get the info about the file
-        let file_name = tr
-            .class("n")
-            .find()
-            .expect("File name attribute was not found");
-        let file_type = tr
-            .class("t")
-            .find()
-            .expect("File type attribute was not found");
-        let file_size = tr
-            .class("s")
-            .find()
-            .expect("File size attribute was not found");
-        let file_modified = tr
-            .class("m")
-            .find()
-            .expect("File modified attribute was not found");
-
-        // Skip table headers and "parent directory" items
-        if file_name.text() == "Name" || file_name.text() == "../" {
-            continue;
-        }
+fn filter_schema_files<'a>(
+    files: &'a [FileEntry],
+    schema: &Schema,
+    date_range: &DateRange,
+) -> Vec<&'a FileEntry> {
+    println!("Filtering files to only gather {} ones", schema.name);
+    let filtered: Vec<&FileEntry> = files
+        .iter()
+        .filter(|x| schema.matches(x))
+        // Files outside of the requested dates are never downloaded
+        .filter(|x| date_range.contains(x.modified.naive_utc()))
+        .collect();
+    println!(
+        "Filtered {} files totalling {} in size.",
+        filtered.len(),
+        helpers::bytes_value_to_size_string(total_size(&filtered) as f64)
+    );
+    return filtered;
+}
 
-        // Make a NaiveDate from the file modified info
-        let file_date =
-            NaiveDate::parse_from_str(file_modified.text().as_str(), "%Y-%b-%d %H:%M:%S").unwrap();
-
-        // Compare the file's creation date to the update 17 release date, if its newer than that update we want it
-        if helpers::date_is_after(file_date, update_17) {
-            // Parse the url from the file_name Node
-            let parsed_url = parse_url(&file_name, url);
-
-            match parsed_url {
-                Some(url) => {
-                    if file_type.text() == "Directory" {
-                        if file_name.text() != "../" {
-                            // Add the next directory's URL to the list to be searched if its not the parent dir
-                            directories.push(url);
-                        }
-                    } else {
-                        // Create a json blob of the file info
-                        let data = json!({
-                            "name": file_name.text(),
-                            "type": file_type.text(),
-                            "size": helpers::string_to_bytes_value(file_size.text()),
-                            "modified": file_modified.text(),
-                            "url": url
-                        });
-
-                        // Push a file blob into the file vector
-                        files.push(data);
-                    }
-                }
-                None => {}
-            }
-        }
+fn total_size(files: &[&FileEntry]) -> u64 {
+    // Calculate the total size of all files we indexed
+    return files.iter().map(|x| x.size).sum();
+}
+
+// Downloads the files and reports how that went. Fails if any of them couldn't be downloaded
+fn download_stage(
+    files: &[&FileEntry],
+    download_dir: &Path,
+    http: &HttpClient,
+    options: &DownloadOptions,
+) -> std::io::Result<()> {
+    let bandwidth = options.max_bandwidth.map(RateLimiter::new);
+    let downloader = options.downloader(download_dir, http, bandwidth.as_ref());
+    println!(
+        "Downloading files to disk with {} threads...",
+        downloader.num_workers
+    );
+
+    // Download the files
+    let report = downloader.download_files(files)?;
+    let failed: Vec<_> = report.failed().collect();
+    println!(
+        "Downloaded {} files, skipped {} we already had and {} failed.",
+        report.downloaded(),
+        report.skipped(),
+        failed.len()
+    );
+    for (file, reason) in &failed {
+        println!("Failed: {}: {}", file.name, reason);
+    }
+    if !failed.is_empty() {
+        return Err(std::io::Error::other(format!(
+            "{} of {} downloads failed",
+            failed.len(),
+            files.len()
+        )));
     }
-    return (files, directories);
+    return Ok(());
+}
+
+// The client for the configured database, and the schema pointed at the database and collection it was given
+fn connect(database: &DatabaseArgs, schema: &SchemaArgs) -> std::io::Result<(Client, Schema)> {
+    let settings = database.settings()?;
+    let mut schema = schema.schema();
+    settings.apply_to(&mut schema);
+    return Ok((settings.client()?, schema));
 }
 
-fn crawl_directory(base_url: &str) -> Vec<Value> {
-    println!("Crawling URL: {}", base_url);
-    // Collect all files in a directory
-    let (mut files, directories) = find_files(base_url);
-    // Run a recursive scan of sub directories and collect all files
-    directories
+fn import_stage(
+    client: &Client,
+    download_dir: &Path,
+    schema: &Schema,
+    index: Option<&CrawlIndex>,
+    num_workers: usize,
+    options: &ImportOptions,
+) -> std::io::Result<()> {
+    // Collect the schema's files in the downloads directory, only the ones in the index if we were given one
+    let downloaded = if index.is_none() && download_dir.exists() {
+        index::local_files(download_dir)?
+    } else {
+        Vec::new()
+    };
+    let candidates = index.map_or(&downloaded, |index| &index.files);
+    let files: Vec<&FileEntry> = candidates
         .iter()
-        .for_each(|dir| files.extend(crawl_directory(&dir)));
+        .filter(|x| schema.matches(x))
+        .filter(|x| download_dir.join(&x.name).is_file())
+        .collect();
+    println!(
+        "Importing {} files into {}.{}...",
+        files.len(),
+        schema.database,
+        schema.collection
+    );
+    // Try to import the files
+    let importer = Importer {
+        client,
+        schema,
+        download_dir,
+        batch_size: options.batch_size,
+        max_reject_rate: options.max_reject_rate,
+        num_workers,
+    };
+    let report = importer.import_files(&files)?;
+    let failed: Vec<_> = report.failed().collect();
+    println!(
+        "Imported {} messages from {} files and skipped {} files that were imported before.",
+        report.inserted(),
+        report.imported(),
+        report.already_imported()
+    );
+    println!(
+        "{} messages were already in the database, {} malformed lines were rejected and {} files failed.",
+        report.duplicates(),
+        report.rejected(),
+        failed.len()
+    );
+    if report.rejected() > 0 {
+        println!(
+            "Rejected lines are logged in {}",
+            download_dir.join(importer::REJECTS_DIR).display()
+        );
+    }
+    for (file, reason) in &failed {
+        println!("Failed: {}: {}", file.name, reason);
+    }
+    if !failed.is_empty() {
+        return Err(std::io::Error::other(format!(
+            "{} of {} imports failed",
+            failed.len(),
+            files.len()
+        )));
+    }
+    return Ok(());
+}
 
-    println!("Indexed {} files", files.len());
-    return files;
+fn dump_stage(client: &Client, schema: &Schema, output: &Path) -> std::io::Result<()> {
+    if !schema.supports_installations_dump() {
+        return Err(std::io::Error::other(format!(
+            "An installations dump can't be generated from {} messages",
+            schema.name
+        )));
+    }
+    println!("Connecting to database...");
+    let count = dumper::dump_installations(client, schema, output)?;
+    println!("Dumped {} signals blobs to {}.", count, output.display());
+    return Ok(());
 }
 
-fn main() -> std::io::Result<()> {
-    // The base url to start our recursive crawl
-    let base_url = "https://edgalaxydata.space/EDDN/";
+fn run_crawl(args: CrawlArgs) -> std::io::Result<()> {
+    crawl_stage(&args.source, &args.http.client()?)?;
+    return Ok(());
+}
 
-    // Start crawling the directories
-    let files: Vec<Value> = crawl_directory(base_url);
+fn run_download(args: DownloadArgs) -> std::io::Result<()> {
+    let http = args.http.client()?;
+    let index = match &args.index {
+        Some(path) => load_index(path)?,
+        None => crawl_stage(&args.source, &http)?,
+    };
+    let signal_files = filter_schema_files(
+        &index.files,
+        &args.schema.schema(),
+        &args.source.date_range(),
+    );
+    return download_stage(&signal_files, &args.dir, &http, &args.download);
+}
 
-    let input = helpers::get_input(
-        "Files have been indexed. Would you like to save their details to a json file? (Y/N): ",
+fn run_import(args: ImportArgs) -> std::io::Result<()> {
+    let (client, schema) = connect(&args.database, &args.schema)?;
+    let index = match &args.index {
+        Some(path) => Some(load_index(path)?),
+        None => None,
+    };
+    return import_stage(
+        &client,
+        &args.dir,
+        &schema,
+        index.as_ref(),
+        cli::import_workers(args.workers),
+        &args.import,
     );
-    match input.trim() {
-        "Y" | "y" => {
-            println!("Saving files to JSON...");
-            // Take the collection of files and turn it into a json blob
-            let json_data = json!({"files": files});
-            // Create a files.json file and dump the json data to it
-            let file = fs::File::create("files.json")?;
-            let result = to_writer_pretty(file, &json_data);
-            if result.is_ok() {
-                println!("Dumped {} file blobs to disk.", files.len());
-            } else {
-                println!("Error writing file blobs to disk:\n {:?}", result);
+}
+
+fn run_dump(args: DumpArgs) -> std::io::Result<()> {
+    let (client, schema) = connect(&args.database, &args.schema)?;
+    return dump_stage(&client, &schema, &args.output);
+}
+
+fn run_all(args: AllArgs) -> std::io::Result<()> {
+    let (client, schema) = connect(&args.database, &args.schema)?;
+    let http = args.http.client()?;
+    let index = match &args.index {
+        Some(path) => load_index(path)?,
+        None => crawl_stage(&args.source, &http)?,
+    };
+
+    // Files that failed to download or import don't hold up the stages after, the run still fails at the end
+    let mut download_result = Ok(());
+    if !args.skip_download {
+        let signal_files = filter_schema_files(&index.files, &schema, &args.source.date_range());
+        download_result = download_stage(&signal_files, &args.dir, &http, &args.download);
+    }
+    let mut import_result = Ok(());
+    if !args.skip_import {
+        // A saved index pins the import to the files listed in it, like it does for the download
+        let pinned = args.index.as_ref().map(|_| &index);
+        import_result = import_stage(
+            &client,
+            &args.dir,
+            &schema,
+            pinned,
+            cli::import_workers(args.import_workers),
+            &args.import,
+        );
+    }
+    if !args.skip_dump {
+        if schema.supports_installations_dump() {
+            dump_stage(&client, &schema, &args.output)?;
+        } else {
+            println!(
+                "Not generating an installations dump for {} messages...",
+                schema.name
+            );
+        }
+    }
+    return download_result.and(import_result);
+}
+
+// Compares the index to the manifest to show what is left to do for each file
+fn run_status(args: StatusArgs) -> std::io::Result<()> {
+    let schema = args.schema.schema();
+    let index = load_index(&args.index)?;
+    let manifest = Manifest::load(&args.dir)?;
+
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    let mut left = Vec::new();
+    for file in index.files.iter().filter(|x| schema.matches(x)) {
+        let entry = manifest.files.get(&file.name);
+        let status = entry.map_or(FileStatus::Pending, |x| x.status);
+        // A file that changed on the server since we downloaded it will be downloaded again
+        let state = match entry {
+            Some(entry) if entry.downloaded_at.is_some() && !entry.matches_listing(file) => {
+                "Changed on the server"
             }
+            _ => status.describe(),
+        };
+        match counts.iter_mut().find(|(x, _)| *x == state) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((state, 1)),
         }
-        "N" | "n" => {
-            println!("Not saving files to JSON...");
+        if state != FileStatus::Imported.describe() {
+            let error = entry.and_then(|x| x.last_error.as_deref());
+            left.push((file, state, error));
         }
-        _ => println!("Invalid input. Please enter Y or N."),
     }
 
-    println!("Filtering files to only gather relevant ones");
-    let signal_files: Vec<&Value> = files
-        .iter()
-        .filter(|x| x["name"].to_string().contains("FSSSignalDiscovered"))
-        .filter(|x| !x["name"].to_string().contains("Test"))
-        .collect();
+    println!("{} files:", schema.name);
+    for (state, count) in &counts {
+        println!("  {}: {}", state, count);
+    }
+    for (file, state, error) in &left {
+        match error {
+            Some(error) => println!("{}: {} ({})", file.name, state, error),
+            None => println!("{}: {}", file.name, state),
+        }
+    }
+    if left.is_empty() {
+        println!("Nothing left to do.");
+    }
+    return Ok(());
+}
 
-    // Calculate the total size of all files we indexed
-    let mut total_size: f64 = 0.0;
-    signal_files
-        .iter()
-        .for_each(|x| total_size += x["size"].as_f64().unwrap());
+fn run_interactive(database: DatabaseArgs) -> std::io::Result<()> {
+    let download_dir = Path::new("downloads");
+    let source = SourceArgs::default();
+    let mut schema = Schema::new(schema::DEFAULT_SCHEMA);
+    let settings = database.settings()?;
+    settings.apply_to(&mut schema);
+    let client = settings.client()?;
+    let http = HttpArgs::default().client()?;
 
-    let input = helpers::get_input(&format!(
-        "Filtered {} files totalling {} in size. Would you like to download them? (Y/N): ",
-        signal_files.len(),
-        helpers::bytes_value_to_size_string(total_size)
-    ));
+    let index = crawl_stage(&source, &http)?;
+    let signal_files = filter_schema_files(&index.files, &schema, &source.date_range());
+
+    let input = helpers::get_input("Would you like to download them? (Y/N): ");
     match input.trim() {
         "Y" | "y" => {
-            // The number of threads to use in the download. Defaults to: num_cpus - 1
-            // (though if you have any more than a few cores and slow internet, you may want to lower this)
-            let num_workers = num_cpus::get() - 1;
-            println!("Downloading files to disk with {} threads...", num_workers);
-
-            // Initialize the two file info vectors
-            let mut urls: Vec<&str> = Vec::new();
-            let mut names: Vec<&str> = Vec::new();
-            // Populate the file info vectors with the info we need
-            signal_files.iter().for_each(|x| {
-                urls.push(x["url"].as_str().unwrap());
-                names.push(x["name"].as_str().unwrap());
-            });
-            // Download the files
-            let result = downloader::download_files_in_parallel(&urls, &names, num_workers);
-
-            match result {
-                Ok(_) => println!("Successfully downloaded {} files!", urls.len()),
-                Err(e) => println!("Problem downloading files! {:?}", e),
+            let options = DownloadOptions::default();
+            if let Err(e) = download_stage(&signal_files, download_dir, &http, &options) {
+                println!("Problem downloading files! {}", e);
             }
         }
         "N" | "n" => println!("Not saving files to Disk..."),
         _ => println!("Invalid input. Please enter Y or N."),
     }
+
     let input = helpers::get_input("Do you want to import any downloaded files? THIS IS A CONSIDERABLE TIME INVESTMENT! (Y/N): ");
     match input.trim() {
         "Y" | "y" => {
-            let num_workers = num_cpus::get() / 2;
-            // Create the mongo DB client we will use
-            let client = Client::with_uri_str("mongodb://localhost:27017")
-                .expect("Error when creating database client!");
-
-            // Create a new list to fill with file names
-            let mut files = Vec::new();
-            // Populate the list with files in the downloads directory
-            let dir = fs::read_dir("downloads/").unwrap();
-            for file in dir {
-                let file = file.unwrap();
-                let path = file.path();
-                if path.is_file() {
-                    files.push(path.into_os_string().into_string().unwrap());
-                }
+            if let Err(e) = import_stage(
+                &client,
+                download_dir,
+                &schema,
+                None,
+                cli::import_workers(None),
+                &ImportOptions::default(),
+            ) {
+                println!("Problem importing files! {}", e);
             }
-            println!("Importing {} files...", files.len());
-            // Try to import the files
-            let _ = importer::import_files(&client, &files, num_workers)
-                .expect("Error when inserting files into DB!");
         }
         "N" | "n" => println!("Not importing files to DB..."),
         _ => println!("Invalid input. Please enter Y or N."),
     }
 
     let input = helpers::get_input("Would you like to generate an installations dump? (Y/N): ");
-
     match input.trim() {
-        "Y" | "y" => {
-            println!("Connecting to database...");
-            // Create the mongo DB client we will use
-            let client = Client::with_uri_str("mongodb://localhost:27017")
-                .expect("Error when creating database client!");
-            let db = client.database("FSSSignalDiscovered");
-            let collection: Collection<Document> = db.collection("rust_test");
-
-            // Define the query
-            let query = doc! {"message.signals.SignalType": "Installation"};
-
-            // Define the projection
-            let projection = doc! {
-                "message.StarSystem": 1,
-                "message.SystemAddress": 1,
-                "message.StarPos": 1,
-                "message.signals": {
-                    "$filter": {
-                        "input": "$message.signals",
-                        "as": "signal",
-                        "cond": {
-                            "$eq": ["$$signal.SignalType", "Installation"]
-                        }
-                    }
-                }
-            };
-
-            println!("Generating query...");
-            let find_options = FindOptions::builder().projection(projection).build();
-            let signals = collection.find(query).with_options(find_options).run().unwrap();
-            // Create a new JSON Value to store unique signals
-            let mut unique_signals = serde_json::Map::new();
-
-            println!("Filtering results...");
-
-            let mut i = 0;
-            let mut has_printed = false;
-            // Iterate over the results
-            for result in signals {
-                match result {
-                    Ok(mut signal) => {
-                        // Remove the _id key from the signal
-                        signal.remove("_id");
-
-                        // Convert the signal to a serde_json::Value
-                        let data: serde_json::Value =
-                            serde_json::from_str(&signal.to_string()).unwrap();
-
-                        // Get the StarSystem from the message key in the data
-                        let star_system =
-                            &data["message"]["StarSystem"].as_str().unwrap().to_string();
-
-                        // Check if the star system is in the unique_signals JSON value
-                        if !unique_signals.contains_key(star_system) {
-                            // If it isn't, add it with the data as the value
-                            unique_signals.insert(star_system.to_string(), data);
-                            // Print a message every 100 results
-                            i += 1;
-                            has_printed = false;
-                        } else {
-                            // If it is, check the timestamp of the current signal and the one in unique_signals
-                            let current_timestamp = &data["message"]["signals"][0]["timestamp"];
-                            let stored_timestamp =
-                                &unique_signals[star_system]["message"]["signals"][0]["timestamp"];
-
-                            // Parse the timestamps as NaiveDate
-                            let current_date = NaiveDate::parse_from_str(
-                                current_timestamp.as_str().unwrap(),
-                                "%Y-%m-%dT%H:%M:%SZ",
-                            )
-                            .unwrap();
-                            let stored_date = NaiveDate::parse_from_str(
-                                stored_timestamp.as_str().unwrap(),
-                                "%Y-%m-%dT%H:%M:%SZ",
-                            )
-                            .unwrap();
-
-                            // If the current signal is newer, replace the one in unique_signals
-                            if helpers::date_is_after(current_date, stored_date) {
-                                unique_signals.insert(star_system.to_string(), data);
-                                i += 1;
-                                has_printed = false;
-                            }
-                        }
-                        // Print a message every 1000 results we process
-                        if i % 1000 == 0 && !has_printed {
-                            println!("Processed {} total items that are new or should replace existing ones...", i);
-                            has_printed = true;
-                        }
-                    }
-                    Err(e) => println!("Error processing document: {:?}", e),
-                }
-            }
-
-            // Print a message with the number of signals remaining in unique_signals
-            println!("Number of unique signals: {}", unique_signals.len());
-            println!("Dumping to disk as requested...");
-            // Create a files.json file and dump the json data to it
-            let file = fs::File::create("installations.json")?;
-            let result = to_writer_pretty(file, &unique_signals);
-            if result.is_ok() {
-                println!("Dumped {} signals blobs to disk.", unique_signals.len());
-            } else {
-                println!("Error writing signal blobs to disk:\n {:?}", result);
-            }
-        }
+        "Y" | "y" => dump_stage(&client, &schema, Path::new("installations.json"))?,
         "N" | "n" => println!("Not generating an installations dump..."),
         _ => println!("Invalid input. Please enter Y or N."),
     }
     return Ok(());
 }
+
+fn main() {
+    let cli = Cli::parse();
+
+    // Without a subcommand we fall back to asking the user about each stage
+    let result = match cli.command {
+        Some(Command::Crawl(args)) => run_crawl(args),
+        Some(Command::Download(args)) => run_download(args),
+        Some(Command::Import(args)) => run_import(args),
+        Some(Command::Dump(args)) => run_dump(args),
+        Some(Command::All(args)) => run_all(args),
+        Some(Command::Status(args)) => run_status(args),
+        None => run_interactive(cli.database),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufReader, Error, Read};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::helpers;
+use crate::index::FileEntry;
+
+// Where the manifest is kept, inside of the downloads directory
+const MANIFEST_FILE: &str = "manifest.json";
+
+// Where a file is at in the pipeline
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    // Listed, but not downloaded yet
+    Pending,
+    DownloadFailed,
+    // Downloaded and waiting to be imported
+    Downloaded,
+    ImportFailed,
+    Imported,
+}
+
+impl FileStatus {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            FileStatus::Pending => return "Not downloaded",
+            FileStatus::DownloadFailed => return "Download failed",
+            FileStatus::Downloaded => return "Waiting to be imported",
+            FileStatus::ImportFailed => return "Import failed",
+            FileStatus::Imported => return "Imported",
+        }
+    }
+}
+
+// Everything we know about one file, from the listing to the import
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub url: String,
+    pub status: FileStatus,
+    // The size and modified time the listing gave for the file
+    pub expected_size: u64,
+    pub modified: DateTime<Utc>,
+    pub downloaded_size: Option<u64>,
+    // SHA-256 of the downloaded file, in hex
+    pub checksum: Option<String>,
+    pub downloaded_at: Option<DateTime<Utc>>,
+    // The validators the server sent with the file, for conditional requests
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub imported_at: Option<DateTime<Utc>>,
+    // What went wrong the last time a stage tried this file
+    pub last_error: Option<String>,
+}
+
+impl ManifestEntry {
+    pub fn new(file: &FileEntry) -> ManifestEntry {
+        return ManifestEntry {
+            url: file.url.clone(),
+            status: FileStatus::Pending,
+            expected_size: file.size,
+            modified: file.modified,
+            downloaded_size: None,
+            checksum: None,
+            downloaded_at: None,
+            etag: None,
+            last_modified: None,
+            imported_at: None,
+            last_error: None,
+        };
+    }
+
+    // Whether the listing still shows the file the way it was when we downloaded it
+    pub fn matches_listing(&self, file: &FileEntry) -> bool {
+        return self.expected_size == file.size && self.modified == file.modified;
+    }
+}
+
+// The state of every file the tool has worked on, by file name. Every stage reads and updates it,
+// so a run can pick up wherever the last one stopped
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    // Loads the manifest kept in `download_dir`, there is none yet before the first download
+    pub fn load(download_dir: &Path) -> Result<Manifest, Error> {
+        let path = download_dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        let file = fs::File::open(path)?;
+        let manifest = serde_json::from_reader(BufReader::new(file))?;
+        return Ok(manifest);
+    }
+
+    pub fn save(&self, download_dir: &Path) -> Result<(), Error> {
+        fs::create_dir_all(download_dir)?;
+        return helpers::save_json(&download_dir.join(MANIFEST_FILE), self);
+    }
+
+    // The entry for a file, added as pending if we haven't seen it before
+    pub fn entry(&mut self, file: &FileEntry) -> &mut ManifestEntry {
+        return self
+            .files
+            .entry(file.name.clone())
+            .or_insert_with(|| ManifestEntry::new(file));
+    }
+}
+
+// SHA-256 of a file, in hex
+pub fn checksum(path: &Path) -> Result<String, Error> {
+    let mut file = BufReader::new(fs::File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    return Ok(format!("{:x}", hasher.finalize()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn file(size: u64, modified: &str) -> FileEntry {
+        let modified = NaiveDateTime::parse_from_str(modified, "%Y-%m-%d %H:%M:%S").unwrap();
+        return FileEntry::new(
+            "Commodity-2024-01-05.jsonl.bz2",
+            "File",
+            size,
+            modified,
+            "https://example.com/Commodity-2024-01-05.jsonl.bz2",
+        );
+    }
+
+    #[test]
+    fn matches_listing_of_the_same_file() {
+        let listed = file(100, "2024-01-05 12:00:00");
+        let entry = ManifestEntry::new(&listed);
+        assert!(entry.matches_listing(&listed));
+        assert!(entry.matches_listing(&file(100, "2024-01-05 12:00:00")));
+    }
+
+    #[test]
+    fn matches_listing_notices_a_changed_file() {
+        let entry = ManifestEntry::new(&file(100, "2024-01-05 12:00:00"));
+        assert!(!entry.matches_listing(&file(200, "2024-01-05 12:00:00")));
+        assert!(!entry.matches_listing(&file(100, "2024-01-05 12:01:00")));
+    }
+
+    #[test]
+    fn entry_keeps_what_is_known_about_a_file() {
+        let mut manifest = Manifest::default();
+        manifest.entry(&file(100, "2024-01-05 12:00:00")).status = FileStatus::Downloaded;
+        // The listing changing doesn't reset the entry, that is up to the stage looking at it
+        let entry = manifest.entry(&file(200, "2024-01-06 12:00:00"));
+        assert_eq!(entry.status, FileStatus::Downloaded);
+        assert_eq!(entry.expected_size, 100);
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt::Display;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
+
+// How often a progress line is logged when we aren't drawing to a terminal
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// The bar of the stage that is running, lines printed while it is drawn have to go through it
+static ACTIVE: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+// What a stage's progress is measured in
+#[derive(Clone, Copy)]
+pub enum Unit {
+    // Bytes transferred or imported, counted against the sizes of the files
+    Bytes,
+    // Directory listings fetched, the total grows as more directories are found
+    Directories,
+}
+
+// The counts that go next to the bar, shared with the log thread
+struct Counts {
+    unit: Unit,
+    files: AtomicU64,
+    failed: AtomicU64,
+    total_files: Option<u64>,
+}
+
+// Progress of one stage: total and completed work, throughput and ETA. Drawn live on a terminal,
+// otherwise logged as a plain line every so often
+pub struct Progress {
+    stage: &'static str,
+    bar: ProgressBar,
+    counts: Arc<Counts>,
+    logger: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+// Prints a line without tearing up the progress display
+pub fn println(message: impl Display) {
+    match &*ACTIVE.lock().unwrap() {
+        Some(bar) if !bar.is_hidden() => bar.println(message.to_string()),
+        _ => println!("{}", message),
+    }
+}
+
+// Like println, for errors
+pub fn eprintln(message: impl Display) {
+    match &*ACTIVE.lock().unwrap() {
+        Some(bar) if !bar.is_hidden() => bar.suspend(|| eprintln!("{}", message)),
+        _ => eprintln!("{}", message),
+    }
+}
+
+impl Counts {
+    fn summary(&self) -> String {
+        let files = self.files.load(Ordering::Relaxed);
+        let mut summary = match (self.unit, self.total_files) {
+            (Unit::Directories, _) => format!("{} files indexed", files),
+            (Unit::Bytes, Some(total)) => format!("{}/{} files", files, total),
+            (Unit::Bytes, None) => format!("{} files", files),
+        };
+        let failed = self.failed.load(Ordering::Relaxed);
+        if failed > 0 {
+            summary.push_str(&format!(", {} failed", failed));
+        }
+        return summary;
+    }
+}
+
+// The plain line logged in place of the live display
+fn log_line(stage: &str, bar: &ProgressBar, counts: &Counts) -> String {
+    let (position, length) = (bar.position(), bar.length().unwrap_or_default());
+    let (done, rate) = match counts.unit {
+        Unit::Bytes => (
+            format!("{}/{}", HumanBytes(position), HumanBytes(length)),
+            format!("{}/s", HumanBytes(bar.per_sec() as u64)),
+        ),
+        Unit::Directories => (
+            format!("{}/{} directories", position, length),
+            format!("{:.1} directories/s", bar.per_sec()),
+        ),
+    };
+    return format!(
+        "{}: {}, {}, {}, ETA {}",
+        stage,
+        done,
+        counts.summary(),
+        rate,
+        HumanDuration(bar.eta())
+    );
+}
+
+impl Progress {
+    pub fn new(stage: &'static str, unit: Unit, total: u64, total_files: Option<u64>) -> Progress {
+        let template = match unit {
+            Unit::Bytes => {
+                "{prefix}: [{bar:30}] {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta} {msg}"
+            }
+            Unit::Directories => {
+                "{prefix}: [{bar:30}] {pos}/{len} directories {per_sec} ETA {eta} {msg}"
+            }
+        };
+        // Draws to stderr, and nowhere if that isn't a terminal
+        let bar = ProgressBar::new(total)
+            .with_style(
+                ProgressStyle::with_template(template)
+                    .unwrap()
+                    .progress_chars("=> "),
+            )
+            .with_prefix(stage);
+        bar.enable_steady_tick(Duration::from_millis(200));
+
+        let counts = Arc::new(Counts {
+            unit,
+            files: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            total_files,
+        });
+        bar.set_message(counts.summary());
+
+        let logger = if bar.is_hidden() {
+            let (stop, stopped) = mpsc::channel::<()>();
+            let (bar, counts) = (bar.clone(), counts.clone());
+            let handle = thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(LOG_INTERVAL) {
+                    println!("{}", log_line(stage, &bar, &counts));
+                }
+            });
+            Some((stop, handle))
+        } else {
+            None
+        };
+
+        *ACTIVE.lock().unwrap() = Some(bar.clone());
+        return Progress {
+            stage,
+            bar,
+            counts,
+            logger,
+        };
+    }
+
+    // Counts work done, in the stage's unit
+    pub fn advance(&self, amount: u64) {
+        self.bar.inc(amount);
+    }
+
+    // More work turned up, like directories found during a crawl
+    pub fn add_total(&self, amount: u64) {
+        self.bar.inc_length(amount);
+    }
+
+    // A file is done with, `failed` ones are counted separately
+    pub fn finish_file(&self, failed: bool) {
+        let counter = if failed {
+            &self.counts.failed
+        } else {
+            &self.counts.files
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.bar.set_message(self.counts.summary());
+    }
+
+    // Counts `count` files for a crawl, which are found rather than finished
+    pub fn add_files(&self, count: u64) {
+        self.counts.files.fetch_add(count, Ordering::Relaxed);
+        self.bar.set_message(self.counts.summary());
+    }
+
+    // Wraps a reader so whatever is read through it counts as progress
+    pub fn reader<R: Read>(&self, inner: R) -> ProgressReader<'_, R> {
+        return ProgressReader {
+            inner,
+            progress: self,
+        };
+    }
+
+    // Stops the log thread, if there is one. Returns whether there was
+    fn stop_logger(&mut self) -> bool {
+        match self.logger.take() {
+            Some((stop, handle)) => {
+                let _ = stop.send(());
+                let _ = handle.join();
+                return true;
+            }
+            None => return false,
+        }
+    }
+
+    // Stops the display and leaves a final line with how the stage went
+    pub fn finish(mut self) {
+        if self.stop_logger() {
+            println!("{}", log_line(self.stage, &self.bar, &self.counts));
+        } else {
+            self.bar.finish();
+        }
+    }
+}
+
+impl Drop for Progress {
+    // A stage that failed part way stops its display too
+    fn drop(&mut self) {
+        self.stop_logger();
+        if !self.bar.is_finished() {
+            self.bar.abandon();
+        }
+        *ACTIVE.lock().unwrap() = None;
+    }
+}
+
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a Progress,
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.advance(read as u64);
+        return Ok(read);
+    }
+}
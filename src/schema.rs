@@ -0,0 +1,108 @@
+use chrono::NaiveDate;
+
+use crate::index::FileEntry;
+
+pub const DEFAULT_SCHEMA: &str = "FSSSignalDiscovered";
+
+// The collection FSSSignalDiscovered messages have always been imported into, kept so existing databases still work
+const LEGACY_COLLECTION: &str = "rust_test";
+
+// An EDDN schema whose archives we index, download and import
+#[derive(Clone, Debug)]
+pub struct Schema {
+    // The name used in archive file names, e.g. "FSSSignalDiscovered" or "Commodity"
+    pub name: String,
+    pub database: String,
+    pub collection: String,
+}
+
+// Splits an archive name like "Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2" into its schema part and date
+pub fn split_archive_name(file_name: &str) -> Option<(&str, NaiveDate)> {
+    // The date is the last "-YYYY-MM-DD" in the name
+    return file_name.match_indices('-').rev().find_map(|(i, _)| {
+        let date = file_name.get(i + 1..i + 11)?;
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+        return Some((&file_name[..i], date));
+    });
+}
+
+impl Schema {
+    pub fn new(name: &str) -> Schema {
+        let collection = if name == DEFAULT_SCHEMA {
+            LEGACY_COLLECTION
+        } else {
+            "messages"
+        };
+        return Schema {
+            name: name.to_string(),
+            database: name.replace('.', "_"),
+            collection: collection.to_string(),
+        };
+    }
+
+    // Whether an archive holds this schema's messages. The schema part of the name has to match exactly,
+    // optionally behind a prefix like "Journal.", so "Journal" doesn't pick up "Journal.FSSSignalDiscovered"
+    pub fn matches(&self, file: &FileEntry) -> bool {
+        if file.name.contains("Test") {
+            return false;
+        }
+        return file.schema.as_ref().is_some_and(|schema| {
+            *schema == self.name || schema.ends_with(&format!(".{}", self.name))
+        });
+    }
+
+    // Messages sent to EDDN's test schemas end up in the same archives, they never belong in the database
+    pub fn accepts_message(&self, schema_ref: &str) -> bool {
+        return !schema_ref.trim_end_matches('/').ends_with("/test");
+    }
+
+    // The installations dump only makes sense for signal messages
+    pub fn supports_installations_dump(&self) -> bool {
+        return self.name == DEFAULT_SCHEMA;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(date: &str) -> NaiveDate {
+        return NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+    }
+
+    #[test]
+    fn split_archive_name_finds_schema_and_date() {
+        assert_eq!(
+            split_archive_name("Journal.FSSSignalDiscovered-2024-01-05.jsonl.bz2"),
+            Some(("Journal.FSSSignalDiscovered", date("2024-01-05")))
+        );
+        assert_eq!(
+            split_archive_name("Commodity-2023-12-31.jsonl"),
+            Some(("Commodity", date("2023-12-31")))
+        );
+    }
+
+    #[test]
+    fn split_archive_name_uses_the_last_date() {
+        assert_eq!(
+            split_archive_name("Journal.Scan-Test-2024-01-01-2024-01-05.jsonl.bz2"),
+            Some(("Journal.Scan-Test-2024-01-01", date("2024-01-05")))
+        );
+        // Dashes after the date aren't part of another date
+        assert_eq!(
+            split_archive_name("Outfitting-2024-01-05-part-2.jsonl"),
+            Some(("Outfitting", date("2024-01-05")))
+        );
+    }
+
+    #[test]
+    fn split_archive_name_needs_a_date() {
+        assert_eq!(split_archive_name("README.txt"), None);
+        assert_eq!(split_archive_name("Journal.Scan-latest.jsonl.bz2"), None);
+        assert_eq!(
+            split_archive_name("Journal.Scan-2024-13-01.jsonl.bz2"),
+            None
+        );
+        assert_eq!(split_archive_name("Journal.Scan-2024-01"), None);
+    }
+}
@@ -0,0 +1,116 @@
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Spreads work out over time so it never goes faster than `rate` units (bytes, requests) per second,
+// no matter how many threads share the limiter
+pub struct RateLimiter {
+    rate: f64,
+    // When the work handed out so far is paid off and the next caller can go
+    next_free: Mutex<Instant>,
+}
+
+// Time that went unused can make up for a short burst afterwards, but no more than this
+const MAX_BURST: Duration = Duration::from_secs(1);
+
+impl RateLimiter {
+    pub fn new(rate: f64) -> RateLimiter {
+        return RateLimiter {
+            rate,
+            next_free: Mutex::new(Instant::now()),
+        };
+    }
+
+    // Blocks until it is `amount` units' turn. The next caller then waits for as long as those units take at the rate
+    pub fn wait(&self, amount: f64) {
+        let delay = {
+            let mut next_free = self.next_free.lock().unwrap();
+            let now = Instant::now();
+            let earliest = now.checked_sub(MAX_BURST).unwrap_or(now);
+            let start = (*next_free).max(earliest);
+            *next_free = start + Duration::from_secs_f64(amount / self.rate);
+            start.saturating_duration_since(now)
+        };
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+}
+
+// A reader that keeps to the rate of a (shared) limiter, counted in bytes
+pub struct ThrottledReader<'a, R> {
+    pub inner: R,
+    pub limiter: Option<&'a RateLimiter>,
+}
+
+impl<R: Read> Read for ThrottledReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(limiter) = self.limiter {
+            limiter.wait(read as f64);
+        }
+        return Ok(read);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_spaces_work_out_at_the_rate() {
+        let limiter = RateLimiter::new(100.0);
+        let started = Instant::now();
+        // The first 10 units go right away, the next ones wait 100ms for each 10 before them
+        for _ in 0..3 {
+            limiter.wait(10.0);
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(190), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn wait_allows_a_burst_after_being_idle() {
+        let limiter = RateLimiter::new(1000.0);
+        thread::sleep(Duration::from_millis(300));
+        let started = Instant::now();
+        // The 300ms that went unused pay for 300 units straight away, the units after them wait their turn
+        limiter.wait(150.0);
+        limiter.wait(150.0);
+        assert!(started.elapsed() < Duration::from_millis(50));
+        limiter.wait(100.0);
+        limiter.wait(100.0);
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[test]
+    fn wait_keeps_to_the_rate_across_threads() {
+        let limiter = RateLimiter::new(1000.0);
+        let started = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..5 {
+                        limiter.wait(10.0);
+                    }
+                });
+            }
+        });
+        // 200 units at 1000 a second, less the first 10 that don't wait
+        assert!(started.elapsed() >= Duration::from_millis(180));
+    }
+
+    #[test]
+    fn throttled_reader_passes_the_data_through() {
+        let limiter = RateLimiter::new(1_000_000.0);
+        let mut reader = ThrottledReader {
+            inner: &b"hello world"[..],
+            limiter: Some(&limiter),
+        };
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+        assert_eq!(read, "hello world");
+    }
+}